@@ -0,0 +1,117 @@
+// Runs the simulation without a window and prints summary statistics
+//
+// cargo run --release --bin headless -- [vehicles] [steps] [dt] [spatial index] [integrator] [seed]
+use std::{env, process, time::Instant};
+
+use autonomous_characters_3d::{
+    integrator::Integrator,
+    simulation::{Simulation, SimulationParams, SimulationSummary},
+    spatial_index::SpatialBackend,
+};
+
+fn main() {
+    let args = env::args().skip(1).collect::<Vec<_>>();
+
+    let vehicles = parse(&args, 0, "vehicles", 1000);
+    let steps = parse(&args, 1, "steps", 1000);
+    let dt = parse(&args, 2, "dt", 1.0 / 60.0);
+    let seed = parse(&args, 5, "seed", 0);
+
+    // Names from the menu, case and separators are ignored ("kd-tree", "linear_octree")
+    let normalize = |name: &str| name.to_lowercase().replace([' ', '-', '_'], "");
+    let spatial_index = match args.get(3) {
+        Some(name) => SpatialBackend::ALL
+            .into_iter()
+            .find(|backend| normalize(backend.name()) == normalize(name))
+            .unwrap_or_else(|| {
+                let names = SpatialBackend::ALL.map(|backend| backend.name());
+                exit(&format!(
+                    "unknown spatial index {name}, expected one of {names:?}"
+                ))
+            }),
+        None => SpatialBackend::Naive,
+    };
+    let integrator = match args.get(4) {
+        Some(name) => Integrator::ALL
+            .into_iter()
+            .find(|integrator| normalize(integrator.name()) == normalize(name))
+            .unwrap_or_else(|| {
+                let names = Integrator::ALL.map(|integrator| integrator.name());
+                exit(&format!(
+                    "unknown integrator {name}, expected one of {names:?}"
+                ))
+            }),
+        None => Integrator::default(),
+    };
+
+    let mut simulation = Simulation::new(SimulationParams {
+        spatial_index,
+        integrator,
+        seed,
+        ..Default::default()
+    });
+
+    for _ in 0..vehicles {
+        simulation.spawn_random();
+    }
+
+    print_summary("start", &simulation.summary());
+
+    let start = Instant::now();
+
+    for step in 1..=steps {
+        simulation.step(dt);
+
+        if step % (steps / 10).max(1) == 0 || step == steps {
+            print_summary(&format!("step {step}"), &simulation.summary());
+        }
+    }
+
+    let elapsed = start.elapsed().as_secs_f64();
+
+    println!(
+        "{} vehicles, {} steps of {} s with {} and {} (seed {}): {:.3} ms per step",
+        vehicles,
+        steps,
+        dt,
+        spatial_index.name(),
+        integrator.name(),
+        seed,
+        elapsed * 1000.0 / steps.max(1) as f64
+    );
+}
+
+fn parse<T: std::str::FromStr>(args: &[String], position: usize, name: &str, default: T) -> T {
+    match args.get(position) {
+        Some(value) => value
+            .parse()
+            .unwrap_or_else(|_| exit(&format!("invalid {name}: {value}"))),
+        None => default,
+    }
+}
+
+fn exit(message: &str) -> ! {
+    eprintln!("{message}");
+    eprintln!("usage: headless [vehicles] [steps] [dt] [spatial index] [integrator] [seed]");
+    process::exit(1);
+}
+
+fn print_summary(label: &str, summary: &SimulationSummary) {
+    println!(
+        "{}: mean speed {:.2}, spread {:.2}, polarization {:.3}, center ({:.1}, {:.1}, {:.1})",
+        label,
+        summary.mean_speed,
+        summary.spread,
+        summary.polarization,
+        summary.center_of_mass.x,
+        summary.center_of_mass.y,
+        summary.center_of_mass.z
+    );
+
+    if summary.unindexed > 0 {
+        println!(
+            "{}: {} vehicles left out of the spatial index, their position is not finite",
+            label, summary.unindexed
+        );
+    }
+}
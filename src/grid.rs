@@ -0,0 +1,458 @@
+use rayon::prelude::*;
+use rustc_hash::FxHashMap;
+
+use crate::{
+    point::Point,
+    spatial_index::{check_finite, sphere_aabb, IndexConfig, SpatialIndex, SpatialIndexError},
+};
+
+// Uniform grids with more cells use larger cells instead
+const MAX_GRID_CELLS: usize = 1 << 22;
+
+// Dense grid over the bounding box of the points. Points are sorted by cell (x first), so
+// every cell and every row of cells along x is a contiguous range of points.
+pub struct UniformGrid<T> {
+    pub cell_size: f32,
+    // Corner of the first cell
+    pub min: Point,
+    // Number of cells along each axis
+    pub dimensions: [usize; 3],
+    // Points of cell i are points[cell_start[i]..cell_start[i + 1]]
+    pub cell_start: Vec<u32>,
+    pub points: Vec<(Point, T)>,
+    // Number of cells with points
+    occupied: usize,
+}
+
+// Grid that only stores occupied cells, so the points can be spread over any distance
+pub struct HashedGrid<T> {
+    pub cell_size: f32,
+    // Range of points in every occupied cell
+    pub cells: FxHashMap<[i64; 3], (u32, u32)>,
+    pub points: Vec<(Point, T)>,
+    // Smallest and largest occupied cell coordinates
+    min_cell: [i64; 3],
+    max_cell: [i64; 3],
+}
+
+// Cell containing the point for cells starting at origin
+fn cell_of(point: &Point, origin: &Point, cell_size: f32) -> [i64; 3] {
+    [
+        ((point.x - origin.x) / cell_size).floor() as i64,
+        ((point.y - origin.y) / cell_size).floor() as i64,
+        ((point.z - origin.z) / cell_size).floor() as i64,
+    ]
+}
+
+// Index of a cell in a uniform grid (x first), None outside the grid
+fn index_of([x, y, z]: [i64; 3], [dx, dy, dz]: [usize; 3]) -> Option<usize> {
+    if x < 0 || y < 0 || z < 0 || x >= dx as i64 || y >= dy as i64 || z >= dz as i64 {
+        return None;
+    }
+
+    Some(x as usize + dx * (y as usize + dy * z as usize))
+}
+
+// Number of cells at Chebyshev distance ring from a cell
+fn ring_cells(ring: i64) -> i128 {
+    if ring == 0 {
+        1
+    } else {
+        (2 * ring as i128 + 1).pow(3) - (2 * ring as i128 - 1).pow(3)
+    }
+}
+
+// Calls visit for the cells between min and max at Chebyshev distance ring from center
+fn for_each_ring_cell(
+    center: [i64; 3],
+    ring: i64,
+    (min, max): ([i64; 3], [i64; 3]),
+    mut visit: impl FnMut([i64; 3]),
+) {
+    let range =
+        |axis: usize| (-ring).max(min[axis] - center[axis])..=ring.min(max[axis] - center[axis]);
+
+    for z in range(2) {
+        for y in range(1) {
+            // Inside the shell only the first and last cell of a row are on the ring
+            if z.abs() == ring || y.abs() == ring {
+                for x in range(0) {
+                    visit([center[0] + x, center[1] + y, center[2] + z]);
+                }
+            } else {
+                for x in [-ring, ring] {
+                    if range(0).contains(&x) {
+                        visit([center[0] + x, center[1] + y, center[2] + z]);
+                    }
+                }
+            }
+        }
+    }
+}
+
+// Searches rings of cells around the cell of the point, skipping rings outside the occupied
+// bounds. Points outside ring r are at least r cells away, so the search stops once the k-th
+// nearest found is closer than that. Once a ring has more cells than there are occupied
+// cells all points are compared instead.
+#[allow(clippy::too_many_arguments)]
+fn k_nearest_in_rings<'a, T>(
+    point: &Point,
+    k: usize,
+    cell_size: f32,
+    center: [i64; 3],
+    bounds: ([i64; 3], [i64; 3]),
+    occupied: usize,
+    points: &'a [(Point, T)],
+    cell: impl Fn([i64; 3]) -> &'a [(Point, T)],
+) -> Vec<&'a T> {
+    let mut nearest: Vec<(f32, &T)> = vec![];
+
+    if k == 0 || points.is_empty() {
+        return vec![];
+    }
+
+    let (min, max) = bounds;
+    let first_ring = (0..3)
+        .map(|axis| (min[axis] - center[axis]).max(center[axis] - max[axis]))
+        .max()
+        .unwrap()
+        .max(0);
+    let last_ring = (0..3)
+        .map(|axis| (center[axis] - min[axis]).max(max[axis] - center[axis]))
+        .max()
+        .unwrap();
+
+    for ring in first_ring..=last_ring {
+        if ring_cells(ring) > occupied as i128 {
+            nearest = points
+                .iter()
+                .map(|(other, data)| (other.distance_squared(point), data))
+                .collect();
+            break;
+        }
+
+        for_each_ring_cell(center, ring, bounds, |index| {
+            for (other, data) in cell(index) {
+                nearest.push((other.distance_squared(point), data));
+            }
+        });
+
+        if nearest.len() >= k {
+            nearest.select_nth_unstable_by(k - 1, |a, b| a.0.total_cmp(&b.0));
+            nearest.truncate(k);
+
+            let searched = ring as f32 * cell_size;
+
+            if nearest[k - 1].0 <= searched * searched {
+                break;
+            }
+        }
+    }
+
+    nearest.sort_by(|a, b| a.0.total_cmp(&b.0));
+    nearest.truncate(k);
+    nearest.into_iter().map(|(_, data)| data).collect()
+}
+
+impl<T: Send> UniformGrid<T> {
+    pub fn build_from(points: Vec<(Point, T)>, cell_size: f32) -> Result<Self, SpatialIndexError> {
+        check_finite(&points)?;
+
+        let (mut min, mut max) = match points.first() {
+            Some((first, _)) => (first.clone(), first.clone()),
+            None => (Point::ZERO, Point::ZERO),
+        };
+
+        for (point, _) in points.iter() {
+            min.x = min.x.min(point.x);
+            min.y = min.y.min(point.y);
+            min.z = min.z.min(point.z);
+            max.x = max.x.max(point.x);
+            max.y = max.y.max(point.y);
+            max.z = max.z.max(point.z);
+        }
+
+        // The last cell is computed like the cells of the points, so all of them are inside
+        let mut cell_size = cell_size.max(f32::MIN_POSITIVE);
+        let dimensions = loop {
+            let dimensions = cell_of(&max, &min, cell_size).map(|cell| cell.saturating_add(1));
+
+            if dimensions
+                .iter()
+                .map(|cells| *cells as f64)
+                .product::<f64>()
+                <= MAX_GRID_CELLS as f64
+            {
+                break dimensions.map(|cells| cells as usize);
+            }
+
+            cell_size *= 2.0;
+        };
+
+        let mut grid = Self {
+            cell_size,
+            min,
+            dimensions,
+            cell_start: vec![],
+            points: vec![],
+            occupied: 0,
+        };
+
+        let mut points = points
+            .into_par_iter()
+            .map(|(point, data)| {
+                let cell = cell_of(&point, &grid.min, cell_size);
+                (index_of(cell, dimensions).unwrap(), point, data)
+            })
+            .collect::<Vec<_>>();
+        points.par_sort_unstable_by_key(|(cell, _, _)| *cell);
+
+        // Start of every cell, empty cells start where the next occupied cell does
+        let cell_count = dimensions.iter().product::<usize>();
+        grid.cell_start = Vec::with_capacity(cell_count + 1);
+
+        for (i, (cell, _, _)) in points.iter().enumerate() {
+            if grid.cell_start.len() <= *cell {
+                grid.occupied += 1;
+            }
+
+            while grid.cell_start.len() <= *cell {
+                grid.cell_start.push(i as u32);
+            }
+        }
+
+        grid.cell_start.resize(cell_count + 1, points.len() as u32);
+        grid.points = points
+            .into_iter()
+            .map(|(_, point, data)| (point, data))
+            .collect();
+
+        Ok(grid)
+    }
+}
+
+impl<T> UniformGrid<T> {
+    fn cell(&self, cell: [i64; 3]) -> &[(Point, T)] {
+        match index_of(cell, self.dimensions) {
+            Some(index) => {
+                let start = self.cell_start[index] as usize;
+                let end = self.cell_start[index + 1] as usize;
+                &self.points[start..end]
+            }
+            None => &[],
+        }
+    }
+
+    pub fn for_each_neighbor<F>(&self, point: &Point, radius: f32, mut visit: F)
+    where
+        F: FnMut(&T),
+    {
+        self.for_each_point_within_radius(point, radius, |_, data| visit(data));
+    }
+
+    // Same as for_each_neighbor, also passing the position of every point
+    pub fn for_each_point_within_radius<F>(&self, point: &Point, radius: f32, mut visit: F)
+    where
+        F: FnMut(&Point, &T),
+    {
+        let clamp = |cell: [i64; 3]| {
+            [0, 1, 2].map(|axis| cell[axis].clamp(0, self.dimensions[axis] as i64 - 1))
+        };
+        let (min, max) = sphere_aabb(point, radius);
+
+        let low = cell_of(&min, &self.min, self.cell_size);
+        let high = cell_of(&max, &self.min, self.cell_size);
+
+        // The sphere does not touch the grid
+        if (0..3).any(|axis| high[axis] < 0 || low[axis] >= self.dimensions[axis] as i64) {
+            return;
+        }
+
+        let (low, high) = (clamp(low), clamp(high));
+
+        for z in low[2]..=high[2] {
+            for y in low[1]..=high[1] {
+                // Cells of a row are next to each other
+                let start =
+                    self.cell_start[index_of([low[0], y, z], self.dimensions).unwrap()] as usize;
+                let end = self.cell_start[index_of([high[0], y, z], self.dimensions).unwrap() + 1]
+                    as usize;
+
+                for (other, data) in self.points[start..end].iter() {
+                    if other.distance_squared(point) <= radius * radius {
+                        visit(other, data);
+                    }
+                }
+            }
+        }
+    }
+
+    pub fn k_nearest(&self, point: &Point, k: usize) -> Vec<&T> {
+        let center = cell_of(point, &self.min, self.cell_size);
+        let last = self.dimensions.map(|cells| cells as i64 - 1);
+
+        k_nearest_in_rings(
+            point,
+            k,
+            self.cell_size,
+            center,
+            ([0, 0, 0], last),
+            self.occupied,
+            &self.points,
+            |cell| self.cell(cell),
+        )
+    }
+}
+
+impl<T: Send + Sync> SpatialIndex<T> for UniformGrid<T> {
+    fn build(points: Vec<(Point, T)>, config: &IndexConfig) -> Result<Self, SpatialIndexError> {
+        Self::build_from(points, config.cell_size)
+    }
+
+    fn for_each_point_within_radius(
+        &self,
+        point: &Point,
+        radius: f32,
+        visit: &mut dyn FnMut(&Point, &T),
+    ) {
+        Self::for_each_point_within_radius(self, point, radius, visit);
+    }
+
+    fn k_nearest(&self, point: &Point, k: usize) -> Vec<&T> {
+        UniformGrid::k_nearest(self, point, k)
+    }
+}
+
+impl<T: Send> HashedGrid<T> {
+    pub fn build_from(points: Vec<(Point, T)>, cell_size: f32) -> Result<Self, SpatialIndexError> {
+        check_finite(&points)?;
+
+        let cell_size = cell_size.max(f32::MIN_POSITIVE);
+
+        let mut points = points
+            .into_par_iter()
+            .map(|(point, data)| (cell_of(&point, &Point::ZERO, cell_size), point, data))
+            .collect::<Vec<_>>();
+        points.par_sort_unstable_by_key(|(cell, _, _)| *cell);
+
+        let mut grid = Self {
+            cell_size,
+            cells: FxHashMap::default(),
+            points: vec![],
+            min_cell: [i64::MAX; 3],
+            max_cell: [i64::MIN; 3],
+        };
+
+        for (i, (cell, _, _)) in points.iter().enumerate() {
+            grid.cells.entry(*cell).or_insert((i as u32, i as u32)).1 = i as u32 + 1;
+
+            grid.min_cell = [0, 1, 2].map(|axis| grid.min_cell[axis].min(cell[axis]));
+            grid.max_cell = [0, 1, 2].map(|axis| grid.max_cell[axis].max(cell[axis]));
+        }
+
+        grid.points = points
+            .into_iter()
+            .map(|(_, point, data)| (point, data))
+            .collect();
+
+        Ok(grid)
+    }
+}
+
+impl<T> HashedGrid<T> {
+    fn cell(&self, cell: [i64; 3]) -> &[(Point, T)] {
+        match self.cells.get(&cell) {
+            Some((start, end)) => &self.points[*start as usize..*end as usize],
+            None => &[],
+        }
+    }
+
+    pub fn for_each_neighbor<F>(&self, point: &Point, radius: f32, mut visit: F)
+    where
+        F: FnMut(&T),
+    {
+        self.for_each_point_within_radius(point, radius, |_, data| visit(data));
+    }
+
+    // Same as for_each_neighbor, also passing the position of every point
+    pub fn for_each_point_within_radius<F>(&self, point: &Point, radius: f32, mut visit: F)
+    where
+        F: FnMut(&Point, &T),
+    {
+        let (min, max) = sphere_aabb(point, radius);
+
+        let low = cell_of(&min, &Point::ZERO, self.cell_size);
+        let high = cell_of(&max, &Point::ZERO, self.cell_size);
+        let low = [0, 1, 2].map(|axis| low[axis].max(self.min_cell[axis]));
+        let high = [0, 1, 2].map(|axis| high[axis].min(self.max_cell[axis]));
+
+        if (0..3).any(|axis| low[axis] > high[axis]) {
+            return;
+        }
+
+        let mut visit_cell = |points: &[(Point, T)]| {
+            for (other, data) in points.iter() {
+                if other.distance_squared(point) <= radius * radius {
+                    visit(other, data);
+                }
+            }
+        };
+
+        let box_cells = (0..3)
+            .map(|axis| (high[axis] - low[axis] + 1) as f64)
+            .product::<f64>();
+
+        // Looking up every cell of a large box is slower than checking the occupied ones
+        if box_cells > self.cells.len() as f64 {
+            for (cell, (start, end)) in self.cells.iter() {
+                if (0..3).all(|axis| cell[axis] >= low[axis] && cell[axis] <= high[axis]) {
+                    visit_cell(&self.points[*start as usize..*end as usize]);
+                }
+            }
+
+            return;
+        }
+
+        for z in low[2]..=high[2] {
+            for y in low[1]..=high[1] {
+                for x in low[0]..=high[0] {
+                    visit_cell(self.cell([x, y, z]));
+                }
+            }
+        }
+    }
+
+    pub fn k_nearest(&self, point: &Point, k: usize) -> Vec<&T> {
+        let center = cell_of(point, &Point::ZERO, self.cell_size);
+
+        k_nearest_in_rings(
+            point,
+            k,
+            self.cell_size,
+            center,
+            (self.min_cell, self.max_cell),
+            self.cells.len(),
+            &self.points,
+            |cell| self.cell(cell),
+        )
+    }
+}
+
+impl<T: Send + Sync> SpatialIndex<T> for HashedGrid<T> {
+    fn build(points: Vec<(Point, T)>, config: &IndexConfig) -> Result<Self, SpatialIndexError> {
+        Self::build_from(points, config.cell_size)
+    }
+
+    fn for_each_point_within_radius(
+        &self,
+        point: &Point,
+        radius: f32,
+        visit: &mut dyn FnMut(&Point, &T),
+    ) {
+        Self::for_each_point_within_radius(self, point, radius, visit);
+    }
+
+    fn k_nearest(&self, point: &Point, k: usize) -> Vec<&T> {
+        HashedGrid::k_nearest(self, point, k)
+    }
+}
@@ -0,0 +1,153 @@
+use nalgebra::Vector3;
+
+// How position and velocity are advanced over one timestep
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Integrator {
+    // Position with the old velocity, first order
+    Euler,
+    // Velocity first, position with the new velocity, first order
+    #[default]
+    SemiImplicitEuler,
+    // Acceleration at the start and the end of the step, second order
+    VelocityVerlet,
+    // Classic Runge-Kutta, four evaluations per step, fourth order
+    Rk4,
+}
+
+impl Integrator {
+    pub const ALL: [Integrator; 4] = [
+        Integrator::Euler,
+        Integrator::SemiImplicitEuler,
+        Integrator::VelocityVerlet,
+        Integrator::Rk4,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Integrator::Euler => "Explicit Euler",
+            Integrator::SemiImplicitEuler => "Semi-implicit Euler",
+            Integrator::VelocityVerlet => "Velocity Verlet",
+            Integrator::Rk4 => "RK4",
+        }
+    }
+
+    // Advances position and velocity by dt. The acceleration may depend on both, it is
+    // evaluated once (Euler), twice (Verlet) or four times (RK4) per step. Every velocity is
+    // limited before it moves the position, e.g. to a maximum speed.
+    pub fn step<A, L>(
+        &self,
+        position: &mut Vector3<f32>,
+        velocity: &mut Vector3<f32>,
+        dt: f32,
+        mut acceleration: A,
+        limit: L,
+    ) where
+        A: FnMut(&Vector3<f32>, &Vector3<f32>) -> Vector3<f32>,
+        L: Fn(Vector3<f32>) -> Vector3<f32>,
+    {
+        let (x, v) = (*position, limit(*velocity));
+
+        match self {
+            Integrator::Euler => {
+                let a = acceleration(&x, &v);
+
+                *position = x + v * dt;
+                *velocity = limit(v + a * dt);
+            }
+            Integrator::SemiImplicitEuler => {
+                let a = acceleration(&x, &v);
+
+                *velocity = limit(v + a * dt);
+                *position = x + *velocity * dt;
+            }
+            Integrator::VelocityVerlet => {
+                let a = acceleration(&x, &v);
+
+                *position = x + limit(v + a * (0.5 * dt)) * dt;
+
+                // The velocity at the end of the step is predicted for velocity dependent forces
+                let end = acceleration(position, &limit(v + a * dt));
+                *velocity = limit(v + (a + end) * (0.5 * dt));
+            }
+            Integrator::Rk4 => {
+                let half = 0.5 * dt;
+
+                let (k1_x, k1_v) = (v, acceleration(&x, &v));
+                let (k2_x, k2_v) = {
+                    let v2 = limit(v + k1_v * half);
+                    (v2, acceleration(&(x + k1_x * half), &v2))
+                };
+                let (k3_x, k3_v) = {
+                    let v3 = limit(v + k2_v * half);
+                    (v3, acceleration(&(x + k2_x * half), &v3))
+                };
+                let (k4_x, k4_v) = {
+                    let v4 = limit(v + k3_v * dt);
+                    (v4, acceleration(&(x + k3_x * dt), &v4))
+                };
+
+                *position = x + (k1_x + (k2_x + k3_x) * 2.0 + k4_x) * (dt / 6.0);
+                *velocity = limit(v + (k1_v + (k2_v + k3_v) * 2.0 + k4_v) * (dt / 6.0));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Damped spring, the acceleration depends on position and velocity
+    const STIFFNESS: f32 = 1.0;
+    const DAMPING: f32 = 0.2;
+    const DURATION: f32 = 4.0;
+
+    fn exact(t: f32) -> f32 {
+        let decay = DAMPING / 2.0;
+        let frequency = (STIFFNESS - decay * decay).sqrt();
+
+        (-decay * t).exp() * ((frequency * t).cos() + decay / frequency * (frequency * t).sin())
+    }
+
+    fn error(integrator: Integrator, dt: f32) -> f32 {
+        let mut position = Vector3::new(1.0, 0.0, 0.0);
+        let mut velocity = Vector3::zeros();
+        let steps = (DURATION / dt).round() as usize;
+
+        for _ in 0..steps {
+            integrator.step(
+                &mut position,
+                &mut velocity,
+                dt,
+                |x, v| -x * STIFFNESS - v * DAMPING,
+                |v| v,
+            );
+        }
+
+        (position.x - exact(DURATION)).abs()
+    }
+
+    #[test]
+    fn trajectories_converge_as_dt_shrinks() {
+        for (integrator, order) in Integrator::ALL.into_iter().zip([1.0, 1.0, 2.0, 4.0]) {
+            let errors = [0.4, 0.2, 0.1].map(|dt| error(integrator, dt));
+
+            assert!(
+                errors[2] < errors[1] && errors[1] < errors[0],
+                "{integrator:?} {errors:?}"
+            );
+
+            // Halving dt divides the error by about 2^order
+            let observed = (errors[0] / errors[1]).log2();
+            assert!(observed > order - 0.5, "{integrator:?} {observed}");
+        }
+    }
+
+    #[test]
+    fn higher_orders_are_more_accurate() {
+        let errors = Integrator::ALL.map(|integrator| error(integrator, 0.1));
+
+        assert!(errors[2] < errors[0] && errors[2] < errors[1], "{errors:?}");
+        assert!(errors[3] < errors[2], "{errors:?}");
+    }
+}
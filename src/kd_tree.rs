@@ -0,0 +1,196 @@
+use crate::{
+    point::Point,
+    spatial_index::{check_finite, IndexConfig, SpatialIndex, SpatialIndexError},
+};
+
+// Ranges with more points are split on separate threads
+const PARALLEL_BUILD_THRESHOLD: usize = 1024;
+
+// Balanced k-d tree stored implicitly in one vector. The median of every range splits it
+// into the ranges before and after it, the split axis cycles through x, y and z.
+pub struct KdTree<T> {
+    // Ranges with at most this many points are not split
+    pub leaf_size: usize,
+    pub points: Vec<(Point, T)>,
+}
+
+fn axis_value(point: &Point, axis: usize) -> f32 {
+    match axis {
+        0 => point.x,
+        1 => point.y,
+        _ => point.z,
+    }
+}
+
+impl<T: Send> KdTree<T> {
+    pub fn build_from(
+        mut points: Vec<(Point, T)>,
+        leaf_size: usize,
+    ) -> Result<Self, SpatialIndexError> {
+        check_finite(&points)?;
+
+        let leaf_size = leaf_size.max(1);
+        Self::split(&mut points, 0, leaf_size);
+
+        Ok(Self { leaf_size, points })
+    }
+
+    fn split(points: &mut [(Point, T)], depth: usize, leaf_size: usize) {
+        if points.len() <= leaf_size {
+            return;
+        }
+
+        let axis = depth % 3;
+        let middle = points.len() / 2;
+
+        points.select_nth_unstable_by(middle, |(a, _), (b, _)| {
+            axis_value(a, axis).total_cmp(&axis_value(b, axis))
+        });
+
+        let (before, rest) = points.split_at_mut(middle);
+        let after = &mut rest[1..];
+
+        if before.len() > PARALLEL_BUILD_THRESHOLD {
+            rayon::join(
+                || Self::split(before, depth + 1, leaf_size),
+                || Self::split(after, depth + 1, leaf_size),
+            );
+        } else {
+            Self::split(before, depth + 1, leaf_size);
+            Self::split(after, depth + 1, leaf_size);
+        }
+    }
+}
+
+impl<T> KdTree<T> {
+    pub fn for_each_neighbor<F>(&self, point: &Point, radius: f32, mut visit: F)
+    where
+        F: FnMut(&T),
+    {
+        self.for_each_point_within_radius(point, radius, |_, data| visit(data));
+    }
+
+    // Same as for_each_neighbor, also passing the position of every point
+    pub fn for_each_point_within_radius<F>(&self, point: &Point, radius: f32, mut visit: F)
+    where
+        F: FnMut(&Point, &T),
+    {
+        self.visit_range(0, self.points.len(), 0, point, radius, &mut visit);
+    }
+
+    fn visit_range<F>(
+        &self,
+        start: usize,
+        end: usize,
+        depth: usize,
+        point: &Point,
+        radius: f32,
+        visit: &mut F,
+    ) where
+        F: FnMut(&Point, &T),
+    {
+        if end - start <= self.leaf_size {
+            for (other, data) in self.points[start..end].iter() {
+                if other.distance_squared(point) <= radius * radius {
+                    visit(other, data);
+                }
+            }
+
+            return;
+        }
+
+        let middle = start + (end - start) / 2;
+        let (median, data) = &self.points[middle];
+        let difference = axis_value(point, depth % 3) - axis_value(median, depth % 3);
+
+        if median.distance_squared(point) <= radius * radius {
+            visit(median, data);
+        }
+
+        if difference <= radius {
+            self.visit_range(start, middle, depth + 1, point, radius, visit);
+        }
+
+        if -difference <= radius {
+            self.visit_range(middle + 1, end, depth + 1, point, radius, visit);
+        }
+    }
+
+    // Returns the k closest points sorted by distance
+    pub fn k_nearest(&self, point: &Point, k: usize) -> Vec<&T> {
+        let mut nearest = Vec::with_capacity(k + 1);
+
+        if k > 0 {
+            self.nearest_in_range(0, self.points.len(), 0, point, k, &mut nearest);
+        }
+
+        nearest
+            .into_iter()
+            .map(|(_, index)| &self.points[index].1)
+            .collect()
+    }
+
+    // Keeps nearest sorted by distance and at most k long
+    fn nearest_in_range(
+        &self,
+        start: usize,
+        end: usize,
+        depth: usize,
+        point: &Point,
+        k: usize,
+        nearest: &mut Vec<(f32, usize)>,
+    ) {
+        let mut offer = |index: usize| {
+            let distance = self.points[index].0.distance_squared(point);
+
+            if nearest.len() < k || distance < nearest[k - 1].0 {
+                let position = nearest.partition_point(|(other, _)| *other <= distance);
+                nearest.insert(position, (distance, index));
+                nearest.truncate(k);
+            }
+        };
+
+        if end - start <= self.leaf_size {
+            (start..end).for_each(offer);
+            return;
+        }
+
+        let middle = start + (end - start) / 2;
+        let difference =
+            axis_value(point, depth % 3) - axis_value(&self.points[middle].0, depth % 3);
+
+        offer(middle);
+
+        // The side containing the point first, the other one only if it can be closer
+        let (near, far) = if difference <= 0.0 {
+            ((start, middle), (middle + 1, end))
+        } else {
+            ((middle + 1, end), (start, middle))
+        };
+
+        self.nearest_in_range(near.0, near.1, depth + 1, point, k, nearest);
+
+        if nearest.len() < k || difference * difference <= nearest[k - 1].0 {
+            self.nearest_in_range(far.0, far.1, depth + 1, point, k, nearest);
+        }
+    }
+}
+
+impl<T: Send + Sync> SpatialIndex<T> for KdTree<T> {
+    fn build(points: Vec<(Point, T)>, config: &IndexConfig) -> Result<Self, SpatialIndexError> {
+        Self::build_from(points, config.leaf_size)
+    }
+
+    fn for_each_point_within_radius(
+        &self,
+        point: &Point,
+        radius: f32,
+        visit: &mut dyn FnMut(&Point, &T),
+    ) {
+        Self::for_each_point_within_radius(self, point, radius, visit);
+    }
+
+    fn k_nearest(&self, point: &Point, k: usize) -> Vec<&T> {
+        KdTree::k_nearest(self, point, k)
+    }
+}
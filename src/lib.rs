@@ -0,0 +1,15 @@
+pub mod grid;
+pub mod integrator;
+pub mod kd_tree;
+#[cfg(feature = "octree")]
+pub mod linear_octree;
+#[cfg(feature = "octree")]
+pub mod loose_octree;
+#[cfg(feature = "octree")]
+pub mod octree;
+pub mod point;
+pub mod random;
+pub mod simulation;
+pub mod spatial_index;
+#[cfg(test)]
+mod test_utils;
@@ -0,0 +1,659 @@
+use std::{
+    cmp::{Ordering, Reverse},
+    collections::BinaryHeap,
+};
+
+use rayon::prelude::*;
+
+use crate::{
+    octree::{morton_code, OctreeConfig, Plane, Point, SpatialIndexError, ToPoint, MORTON_BITS},
+    spatial_index::{check_finite, IndexConfig, SpatialIndex},
+};
+
+// Pointerless octree stored in two flat vectors. Points are sorted by Morton code, so the
+// points below every node form one contiguous range. The eight children of a node are stored
+// next to each other (in Morton order), so a node only needs the index of its first child.
+// The tree can not be updated, it is built again from all points instead.
+pub struct LinearOctree<T> {
+    pub config: OctreeConfig,
+    // nodes[0] is the root
+    pub nodes: Vec<LinearNode>,
+    pub points: Vec<(Point, T)>,
+}
+
+pub struct LinearNode {
+    // Bounds of the points below the node (min > max if there are none)
+    pub min: Point,
+    pub max: Point,
+    // First of the eight children, 0 for leaves (the root is never a child)
+    pub first_child: u32,
+    pub parent: u32,
+    // Range of the points below the node
+    pub start: u32,
+    pub end: u32,
+}
+
+impl LinearNode {
+    fn empty(parent: usize, start: usize, end: usize) -> Self {
+        Self {
+            min: Point {
+                x: f32::INFINITY,
+                y: f32::INFINITY,
+                z: f32::INFINITY,
+            },
+            max: Point {
+                x: f32::NEG_INFINITY,
+                y: f32::NEG_INFINITY,
+                z: f32::NEG_INFINITY,
+            },
+            first_child: 0,
+            parent: parent as u32,
+            start: start as u32,
+            end: end as u32,
+        }
+    }
+
+    pub fn is_leaf(&self) -> bool {
+        self.first_child == 0
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.start == self.end
+    }
+
+    // Squared distance from point to the closest point of the bounds (0 if inside)
+    fn distance_to_bounds_squared(&self, point: &Point) -> f32 {
+        let dx = (self.min.x - point.x).max(0.0).max(point.x - self.max.x);
+        let dy = (self.min.y - point.y).max(0.0).max(point.y - self.max.y);
+        let dz = (self.min.z - point.z).max(0.0).max(point.z - self.max.z);
+
+        dx * dx + dy * dy + dz * dz
+    }
+}
+
+// Lazy depth-first traversal of the linear octree, the same as QueryIter for the arena octree
+pub struct LinearQueryIter<'a, T, N, P>
+where
+    N: Fn(&LinearNode) -> bool,
+    P: Fn(&Point) -> bool,
+{
+    octree: &'a LinearOctree<T>,
+    next: Option<usize>,
+    points: std::slice::Iter<'a, (Point, T)>,
+    node_filter: N,
+    point_filter: P,
+}
+
+impl<'a, T, N, P> LinearQueryIter<'a, T, N, P>
+where
+    N: Fn(&LinearNode) -> bool,
+    P: Fn(&Point) -> bool,
+{
+    fn accepts(&self, node_index: usize) -> bool {
+        let node = &self.octree.nodes[node_index];
+
+        !node.is_empty() && (self.node_filter)(node)
+    }
+
+    // Next accepted node in depth-first order, siblings are the following nodes of the block
+    fn successor(&self, node_index: usize) -> Option<usize> {
+        let nodes = &self.octree.nodes;
+        let node = &nodes[node_index];
+
+        if !node.is_leaf() {
+            let first = node.first_child as usize;
+
+            if let Some(child) = (first..first + 8).find(|child| self.accepts(*child)) {
+                return Some(child);
+            }
+        }
+
+        let mut current = node_index;
+
+        while current != 0 {
+            let parent = nodes[current].parent as usize;
+            let last = nodes[parent].first_child as usize + 7;
+
+            if let Some(sibling) = (current + 1..=last).find(|sibling| self.accepts(*sibling)) {
+                return Some(sibling);
+            }
+
+            current = parent;
+        }
+
+        None
+    }
+}
+
+impl<'a, T, N, P> Iterator for LinearQueryIter<'a, T, N, P>
+where
+    N: Fn(&LinearNode) -> bool,
+    P: Fn(&Point) -> bool,
+{
+    type Item = (&'a Point, &'a T);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            for (point, data) in self.points.by_ref() {
+                if (self.point_filter)(point) {
+                    return Some((point, data));
+                }
+            }
+
+            let node_index = self.next?;
+            self.next = self.successor(node_index);
+
+            let node = &self.octree.nodes[node_index];
+
+            if node.is_leaf() {
+                self.points = self.octree.points[node.start as usize..node.end as usize].iter();
+            }
+        }
+    }
+}
+
+// Node or point index ordered by distance, used for best-first traversal
+struct QueueItem {
+    distance: f32,
+    index: usize,
+}
+
+impl PartialEq for QueueItem {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for QueueItem {}
+
+impl PartialOrd for QueueItem {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for QueueItem {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.distance.total_cmp(&other.distance)
+    }
+}
+
+impl<T: Send> LinearOctree<T> {
+    // Sorts the points by Morton code inside their bounding box and splits the sorted points
+    // with the same subdivision rules as the arena octree. Points that are not finite are
+    // rejected with SpatialIndexError::OutOfBounds.
+    pub fn build_from<P: ToPoint>(
+        points: Vec<(P, T)>,
+        config: OctreeConfig,
+    ) -> Result<Self, SpatialIndexError> {
+        let points = points
+            .into_iter()
+            .map(|(point, data)| (point.to_point(), data))
+            .collect::<Vec<_>>();
+        check_finite(&points)?;
+
+        let mut octree = Self {
+            config,
+            nodes: vec![LinearNode::empty(0, 0, points.len())],
+            points: vec![],
+        };
+
+        let (mut min, mut max) = match points.first() {
+            Some((first, _)) => (first.clone(), first.clone()),
+            None => return Ok(octree),
+        };
+
+        for (point, _) in points.iter() {
+            min.x = min.x.min(point.x);
+            min.y = min.y.min(point.y);
+            min.z = min.z.min(point.z);
+            max.x = max.x.max(point.x);
+            max.y = max.y.max(point.y);
+            max.z = max.z.max(point.z);
+        }
+
+        let mut points = points
+            .into_par_iter()
+            .map(|(point, data)| (morton_code(&point, &min, &max), point, data))
+            .collect::<Vec<_>>();
+        points.par_sort_unstable_by_key(|(code, _, _)| *code);
+
+        let codes = points.iter().map(|(code, _, _)| *code).collect::<Vec<_>>();
+        octree.points = points
+            .into_iter()
+            .map(|(_, point, data)| (point, data))
+            .collect();
+
+        let size = (max.x - min.x).min(max.y - min.y).min(max.z - min.z);
+        octree.build_node(&codes, 0, 0, size);
+
+        Ok(octree)
+    }
+}
+
+impl<T> LinearOctree<T> {
+    fn build_node(&mut self, codes: &[u64], node_index: usize, depth: usize, size: f32) {
+        let (start, end) = {
+            let node = &self.nodes[node_index];
+            (node.start as usize, node.end as usize)
+        };
+        let child_size = size / (1u64 << (depth + 1)) as f32;
+
+        // Morton codes can not split the points any further than MORTON_BITS levels
+        if end - start <= self.config.max_points
+            || depth >= self.config.max_depth
+            || depth >= MORTON_BITS
+            || child_size < self.config.min_cell_size
+        {
+            let node = &mut self.nodes[node_index];
+
+            for (point, _) in self.points[start..end].iter() {
+                node.min.x = node.min.x.min(point.x);
+                node.min.y = node.min.y.min(point.y);
+                node.min.z = node.min.z.min(point.z);
+                node.max.x = node.max.x.max(point.x);
+                node.max.y = node.max.y.max(point.y);
+                node.max.z = node.max.z.max(point.z);
+            }
+
+            return;
+        }
+
+        // Inside the node all higher bits are equal, so the children are sorted by these three
+        let shift = 3 * (MORTON_BITS - 1 - depth);
+        let first_child = self.nodes.len();
+        let mut child_start = start;
+
+        for octant in 0..8 {
+            let child_end = start
+                + codes[start..end]
+                    .partition_point(|code| ((code >> shift) & 7) as usize <= octant);
+
+            self.nodes
+                .push(LinearNode::empty(node_index, child_start, child_end));
+            child_start = child_end;
+        }
+
+        self.nodes[node_index].first_child = first_child as u32;
+
+        for child_index in first_child..first_child + 8 {
+            self.build_node(codes, child_index, depth + 1, size);
+
+            let child = &self.nodes[child_index];
+            let (child_min, child_max) = (child.min.clone(), child.max.clone());
+            let node = &mut self.nodes[node_index];
+
+            node.min.x = node.min.x.min(child_min.x);
+            node.min.y = node.min.y.min(child_min.y);
+            node.min.z = node.min.z.min(child_min.z);
+            node.max.x = node.max.x.max(child_max.x);
+            node.max.y = node.max.y.max(child_max.y);
+            node.max.z = node.max.z.max(child_max.z);
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.points.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.points.is_empty()
+    }
+
+    fn query<N, P>(&self, node_filter: N, point_filter: P) -> LinearQueryIter<'_, T, N, P>
+    where
+        N: Fn(&LinearNode) -> bool,
+        P: Fn(&Point) -> bool,
+    {
+        let mut iter = LinearQueryIter {
+            octree: self,
+            next: None,
+            points: [].iter(),
+            node_filter,
+            point_filter,
+        };
+
+        if !self.nodes.is_empty() && iter.accepts(0) {
+            iter.next = Some(0);
+        }
+
+        iter
+    }
+
+    // Returns all points within radius
+    pub fn find_within_radius(&self, point: impl ToPoint, radius: f32) -> Vec<&T> {
+        self.neighbors_within_radius(point, radius).collect()
+    }
+
+    // Lazily yields all points within radius without allocating
+    pub fn neighbors_within_radius(
+        &self,
+        point: impl ToPoint,
+        radius: f32,
+    ) -> impl Iterator<Item = &T> + '_ {
+        self.points_within_radius(point, radius)
+            .map(|(_, data)| data)
+    }
+
+    // Same as neighbors_within_radius, also yielding the position of every point
+    pub fn points_within_radius(
+        &self,
+        point: impl ToPoint,
+        radius: f32,
+    ) -> impl Iterator<Item = (&Point, &T)> + '_ {
+        let radius_squared = radius * radius;
+        let (node_point, point) = (point.to_point(), point.to_point());
+
+        self.query(
+            move |node| node.distance_to_bounds_squared(&node_point) <= radius_squared,
+            move |other| other.distance_squared(&point) <= radius_squared,
+        )
+    }
+
+    // Calls visit for every point within radius without allocating
+    pub fn for_each_neighbor<F>(&self, point: impl ToPoint, radius: f32, visit: F)
+    where
+        F: FnMut(&T),
+    {
+        self.neighbors_within_radius(point, radius).for_each(visit);
+    }
+
+    // Returns the k closest points sorted by distance (best-first traversal)
+    pub fn k_nearest(&self, point: impl ToPoint, k: usize) -> Vec<&T> {
+        let point = &point.to_point();
+        let mut nearest: BinaryHeap<QueueItem> = BinaryHeap::with_capacity(k + 1);
+
+        if k > 0 && !self.is_empty() {
+            let mut queue = BinaryHeap::new();
+            queue.push(Reverse(QueueItem {
+                distance: self.nodes[0].distance_to_bounds_squared(point),
+                index: 0,
+            }));
+
+            while let Some(Reverse(item)) = queue.pop() {
+                // No remaining node can contain a closer point
+                if nearest.len() == k && item.distance > nearest.peek().unwrap().distance {
+                    break;
+                }
+
+                let node = &self.nodes[item.index];
+
+                if !node.is_leaf() {
+                    let first = node.first_child as usize;
+
+                    for child_index in first..first + 8 {
+                        let child = &self.nodes[child_index];
+
+                        if !child.is_empty() {
+                            queue.push(Reverse(QueueItem {
+                                distance: child.distance_to_bounds_squared(point),
+                                index: child_index,
+                            }));
+                        }
+                    }
+
+                    continue;
+                }
+
+                for index in node.start as usize..node.end as usize {
+                    let distance = self.points[index].0.distance_squared(point);
+
+                    if nearest.len() < k {
+                        nearest.push(QueueItem { distance, index });
+                    } else if distance < nearest.peek().unwrap().distance {
+                        nearest.pop();
+                        nearest.push(QueueItem { distance, index });
+                    }
+                }
+            }
+        }
+
+        nearest
+            .into_sorted_vec()
+            .iter()
+            .map(|item| &self.points[item.index].1)
+            .collect()
+    }
+
+    // Lazily yields all points inside the axis-aligned box (inclusive)
+    pub fn query_aabb(
+        &self,
+        min: impl ToPoint,
+        max: impl ToPoint,
+    ) -> impl Iterator<Item = (&Point, &T)> + '_ {
+        let (node_min, node_max) = (min.to_point(), max.to_point());
+        let (min, max) = (node_min.clone(), node_max.clone());
+
+        self.query(
+            move |node| {
+                node.min.x <= node_max.x
+                    && node.max.x >= node_min.x
+                    && node.min.y <= node_max.y
+                    && node.max.y >= node_min.y
+                    && node.min.z <= node_max.z
+                    && node.max.z >= node_min.z
+            },
+            move |point| {
+                point.x >= min.x
+                    && point.x <= max.x
+                    && point.y >= min.y
+                    && point.y <= max.y
+                    && point.z >= min.z
+                    && point.z <= max.z
+            },
+        )
+    }
+
+    // Lazily yields all points inside the convex volume bounded by the planes
+    pub fn query_frustum(&self, planes: &[Plane]) -> impl Iterator<Item = (&Point, &T)> + '_ {
+        let node_planes = planes.to_vec();
+        let planes = planes.to_vec();
+
+        self.query(
+            move |node| {
+                node_planes
+                    .iter()
+                    .all(|plane| plane.intersects_bounds(&node.min, &node.max))
+            },
+            move |point| {
+                planes
+                    .iter()
+                    .all(|plane| plane.signed_distance(point) >= 0.0)
+            },
+        )
+    }
+}
+
+impl<T: Send + Sync> SpatialIndex<T> for LinearOctree<T> {
+    fn build(points: Vec<(Point, T)>, config: &IndexConfig) -> Result<Self, SpatialIndexError> {
+        Self::build_from(points, config.octree.clone())
+    }
+
+    fn for_each_point_within_radius(
+        &self,
+        point: &Point,
+        radius: f32,
+        visit: &mut dyn FnMut(&Point, &T),
+    ) {
+        self.points_within_radius(point, radius)
+            .for_each(|(other, data)| visit(other, data));
+    }
+
+    fn k_nearest(&self, point: &Point, k: usize) -> Vec<&T> {
+        LinearOctree::k_nearest(self, point, k)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::Rng;
+
+    use super::*;
+    use crate::octree::Octree;
+    use crate::test_utils::{random_index, random_point, test_rng, SIZE};
+
+    fn random_octree(rng: &mut impl Rng, count: usize) -> (LinearOctree<usize>, Vec<Point>) {
+        random_index(rng, count, random_point, |points| {
+            LinearOctree::build_from(
+                points,
+                OctreeConfig {
+                    max_points: 16,
+                    ..Default::default()
+                },
+            )
+            .unwrap()
+        })
+    }
+
+    #[test]
+    fn leaves_hold_contiguous_ranges() {
+        let mut rng = test_rng();
+        let (octree, points) = random_octree(&mut rng, 20000);
+
+        assert_eq!(octree.len(), points.len());
+
+        for (index, node) in octree.nodes.iter().enumerate() {
+            if node.is_leaf() {
+                assert!(node.end - node.start <= 16);
+
+                for (point, _) in octree.points[node.start as usize..node.end as usize].iter() {
+                    assert!(point.x >= node.min.x && point.x <= node.max.x);
+                    assert!(point.y >= node.min.y && point.y <= node.max.y);
+                    assert!(point.z >= node.min.z && point.z <= node.max.z);
+                }
+            } else {
+                let first = &octree.nodes[node.first_child as usize];
+                let last = &octree.nodes[node.first_child as usize + 7];
+
+                assert_eq!((first.start, last.end), (node.start, node.end));
+                assert_eq!(first.parent as usize, index);
+            }
+        }
+    }
+
+    #[test]
+    fn queries_match_brute_force() {
+        let mut rng = test_rng();
+        let (octree, points) = random_octree(&mut rng, 5000);
+
+        for _ in 0..100 {
+            let center = random_point(&mut rng);
+            let radius = rng.gen::<f32>() * SIZE / 2.0;
+
+            let expected = (0..points.len())
+                .filter(|i| points[*i].distance_squared(&center) <= radius * radius)
+                .collect::<Vec<_>>();
+
+            let mut found = vec![];
+            octree.for_each_neighbor(&center, radius, |i| found.push(*i));
+            found.sort();
+
+            assert_eq!(found, expected);
+
+            let (a, b) = (random_point(&mut rng), random_point(&mut rng));
+            let min = Point {
+                x: a.x.min(b.x),
+                y: a.y.min(b.y),
+                z: a.z.min(b.z),
+            };
+            let max = Point {
+                x: a.x.max(b.x),
+                y: a.y.max(b.y),
+                z: a.z.max(b.z),
+            };
+
+            let expected = (0..points.len())
+                .filter(|i| {
+                    let point = &points[*i];
+
+                    point.x >= min.x
+                        && point.x <= max.x
+                        && point.y >= min.y
+                        && point.y <= max.y
+                        && point.z >= min.z
+                        && point.z <= max.z
+                })
+                .collect::<Vec<_>>();
+
+            let mut found = octree
+                .query_aabb(&min, &max)
+                .map(|(_, i)| *i)
+                .collect::<Vec<_>>();
+            found.sort();
+
+            assert_eq!(found, expected);
+        }
+    }
+
+    #[test]
+    fn k_nearest_matches_arena_octree() {
+        let mut rng = test_rng();
+        let (octree, points) = random_octree(&mut rng, 5000);
+        let arena = Octree::build_from(
+            points.iter().cloned().zip(0..).collect(),
+            octree.config.clone(),
+        )
+        .unwrap();
+
+        for _ in 0..100 {
+            let center = random_point(&mut rng);
+
+            assert_eq!(octree.k_nearest(&center, 10), arena.k_nearest(&center, 10));
+        }
+
+        assert!(octree.k_nearest(random_point(&mut rng), 0).is_empty());
+        assert_eq!(octree.k_nearest(random_point(&mut rng), 10000).len(), 5000);
+    }
+
+    #[test]
+    fn vector_types_give_same_results() {
+        let mut rng = test_rng();
+        let (from_points, points) = random_octree(&mut rng, 500);
+        let from_arrays = LinearOctree::build_from(
+            points
+                .iter()
+                .map(|point| [point.x, point.y, point.z])
+                .zip(0..)
+                .collect(),
+            from_points.config.clone(),
+        )
+        .unwrap();
+        let from_vectors = LinearOctree::build_from(
+            points
+                .iter()
+                .map(|point| nalgebra::Vector3::new(point.x, point.y, point.z))
+                .zip(0..)
+                .collect(),
+            from_points.config.clone(),
+        )
+        .unwrap();
+
+        fn sorted(mut found: Vec<&usize>) -> Vec<&usize> {
+            found.sort();
+            found
+        }
+
+        for _ in 0..20 {
+            let center = random_point(&mut rng);
+            let array = [center.x, center.y, center.z];
+            let vector = nalgebra::Vector3::new(center.x, center.y, center.z);
+
+            let expected = sorted(from_points.find_within_radius(&center, SIZE / 2.0));
+            assert_eq!(
+                sorted(from_arrays.find_within_radius(array, SIZE / 2.0)),
+                expected
+            );
+            assert_eq!(
+                sorted(from_vectors.find_within_radius(vector, SIZE / 2.0)),
+                expected
+            );
+
+            let expected = from_points.k_nearest(&center, 10);
+            assert_eq!(from_arrays.k_nearest(array, 10), expected);
+            assert_eq!(from_vectors.k_nearest(vector, 10), expected);
+
+            let expected = from_points.query_aabb(&center, [SIZE; 3]).count();
+            assert_eq!(from_vectors.query_aabb(vector, [SIZE; 3]).count(), expected);
+        }
+    }
+}
@@ -0,0 +1,833 @@
+use std::{
+    cmp::{Ordering, Reverse},
+    collections::BinaryHeap,
+};
+
+use generational_arena::{Arena, Index};
+
+use crate::{
+    octree::{Point, SpatialIndexError, ToPoint},
+    spatial_index::{sphere_aabb, IndexConfig, SpatialIndex},
+};
+
+// Bounding volume of an object stored in a loose octree
+#[derive(Debug, Clone)]
+pub enum Bounds {
+    Sphere { center: Point, radius: f32 },
+    Box { min: Point, max: Point },
+}
+
+impl Bounds {
+    pub fn sphere(center: impl ToPoint, radius: f32) -> Self {
+        Bounds::Sphere {
+            center: center.to_point(),
+            radius,
+        }
+    }
+
+    // Axis-aligned box from its min and max corner
+    pub fn cuboid(min: impl ToPoint, max: impl ToPoint) -> Self {
+        Bounds::Box {
+            min: min.to_point(),
+            max: max.to_point(),
+        }
+    }
+
+    // Smallest axis-aligned box containing the bounds
+    pub fn aabb(&self) -> (Point, Point) {
+        match self {
+            Bounds::Sphere { center, radius } => sphere_aabb(center, *radius),
+            Bounds::Box { min, max } => (min.clone(), max.clone()),
+        }
+    }
+
+    // Center of the sphere or the box
+    pub fn center(&self) -> Point {
+        match self {
+            Bounds::Sphere { center, .. } => center.clone(),
+            Bounds::Box { min, max } => Point {
+                x: (min.x + max.x) / 2.0,
+                y: (min.y + max.y) / 2.0,
+                z: (min.z + max.z) / 2.0,
+            },
+        }
+    }
+
+    pub fn is_finite(&self) -> bool {
+        match self {
+            Bounds::Sphere { center, radius } => center.is_finite() && radius.is_finite(),
+            Bounds::Box { min, max } => min.is_finite() && max.is_finite(),
+        }
+    }
+
+    // Squared distance from the point to the closest point of the bounds (0 if inside)
+    pub fn distance_squared(&self, point: &Point) -> f32 {
+        match self {
+            // Points skip the square root, so their distances match Point::distance_squared exactly
+            Bounds::Sphere { center, radius } if *radius <= 0.0 => center.distance_squared(point),
+            Bounds::Sphere { center, radius } => (center.distance_squared(point).sqrt() - radius)
+                .max(0.0)
+                .powi(2),
+            Bounds::Box { min, max } => box_distance_squared(min, max, point),
+        }
+    }
+
+    // Touching bounds overlap
+    pub fn intersects(&self, other: &Bounds) -> bool {
+        match (self, other) {
+            (
+                Bounds::Sphere { center, radius },
+                Bounds::Sphere {
+                    center: other_center,
+                    radius: other_radius,
+                },
+            ) => center.distance_squared(other_center) <= (radius + other_radius).powi(2),
+            (Bounds::Sphere { center, radius }, Bounds::Box { min, max })
+            | (Bounds::Box { min, max }, Bounds::Sphere { center, radius }) => {
+                box_distance_squared(min, max, center) <= radius * radius
+            }
+            (
+                Bounds::Box { min, max },
+                Bounds::Box {
+                    min: other_min,
+                    max: other_max,
+                },
+            ) => boxes_overlap(min, max, other_min, other_max),
+        }
+    }
+}
+
+// Points are spheres with radius 0
+impl<P: ToPoint> From<P> for Bounds {
+    fn from(point: P) -> Self {
+        Bounds::sphere(point, 0.0)
+    }
+}
+
+fn box_distance_squared(min: &Point, max: &Point, point: &Point) -> f32 {
+    let dx = (min.x - point.x).max(0.0).max(point.x - max.x);
+    let dy = (min.y - point.y).max(0.0).max(point.y - max.y);
+    let dz = (min.z - point.z).max(0.0).max(point.z - max.z);
+
+    dx * dx + dy * dy + dz * dz
+}
+
+fn boxes_overlap(min: &Point, max: &Point, other_min: &Point, other_max: &Point) -> bool {
+    min.x <= other_max.x
+        && max.x >= other_min.x
+        && min.y <= other_max.y
+        && max.y >= other_min.y
+        && min.z <= other_max.z
+        && max.z >= other_min.z
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct LooseOctreeConfig {
+    // The loose bounds of a node are this many times larger than the node, at least 1
+    pub looseness: f32,
+    // Nodes at this depth (root is 0) are never split
+    pub max_depth: usize,
+}
+
+impl Default for LooseOctreeConfig {
+    fn default() -> Self {
+        Self {
+            looseness: 2.0,
+            max_depth: 8,
+        }
+    }
+}
+
+// Octree for objects with extents (vehicles of different sizes, obstacles, targets). Every
+// object is stored in the deepest node whose loose bounds (the node enlarged by looseness
+// around its center) fully contain it, so an object is stored exactly once and queries only
+// have to look at nodes whose loose bounds overlap the query. Objects outside the loose
+// bounds of the root are kept in the root. Different kinds of objects can share one tree
+// by using an enum as T.
+pub struct LooseOctree<T> {
+    pub config: LooseOctreeConfig,
+    pub root: Index,
+    pub nodes: Arena<LooseNode>,
+    pub objects: Arena<LooseObject<T>>,
+}
+
+pub struct LooseNode {
+    pub center: Point,
+    // Half of the edge length of the (not loosened) node
+    pub half_size: f32,
+    pub parent: Option<Index>,
+    // Created when the first object is stored below them, order as in octant
+    pub children: [Option<Index>; 8],
+    pub objects: Vec<Index>,
+}
+
+#[derive(Debug)]
+pub struct LooseObject<T> {
+    pub bounds: Bounds,
+    pub data: T,
+    // Node that currently holds the object
+    pub node: Index,
+}
+
+// Node or object index ordered by distance, used for best-first traversal
+struct QueueItem {
+    distance: f32,
+    index: Index,
+}
+
+impl PartialEq for QueueItem {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for QueueItem {}
+
+impl PartialOrd for QueueItem {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for QueueItem {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.distance.total_cmp(&other.distance)
+    }
+}
+
+// Index of the child on the side of the point (bit 0, 1 and 2 for the upper x, y and z half)
+fn octant(center: &Point, point: &Point) -> usize {
+    (point.x >= center.x) as usize
+        + 2 * (point.y >= center.y) as usize
+        + 4 * (point.z >= center.z) as usize
+}
+
+fn child_center(center: &Point, octant: usize, half_size: f32) -> Point {
+    let offset = |bit: usize| {
+        if octant & bit == 0 {
+            -half_size
+        } else {
+            half_size
+        }
+    };
+
+    Point {
+        x: center.x + offset(1),
+        y: center.y + offset(2),
+        z: center.z + offset(4),
+    }
+}
+
+impl<T> LooseOctree<T> {
+    pub fn new(center: impl ToPoint, half_size: f32, config: LooseOctreeConfig) -> Self {
+        let config = LooseOctreeConfig {
+            looseness: config.looseness.max(1.0),
+            ..config
+        };
+
+        let mut nodes = Arena::new();
+        let root = nodes.insert(LooseNode {
+            center: center.to_point(),
+            half_size,
+            parent: None,
+            children: [None; 8],
+            objects: vec![],
+        });
+
+        Self {
+            config,
+            root,
+            nodes,
+            objects: Arena::new(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.objects.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.objects.is_empty()
+    }
+
+    pub fn get(&self, index: Index) -> Option<&T> {
+        self.objects.get(index).map(|object| &object.data)
+    }
+
+    pub fn get_mut(&mut self, index: Index) -> Option<&mut T> {
+        self.objects.get_mut(index).map(|object| &mut object.data)
+    }
+
+    // Bounds that are not finite are rejected with SpatialIndexError::OutOfBounds. Points are
+    // inserted as spheres with radius 0.
+    pub fn insert(
+        &mut self,
+        bounds: impl Into<Bounds>,
+        data: T,
+    ) -> Result<Index, SpatialIndexError> {
+        let bounds = bounds.into();
+
+        if !bounds.is_finite() {
+            let (min, _) = bounds.aabb();
+            return Err(SpatialIndexError::OutOfBounds(min));
+        }
+
+        let node = self.node_for(&bounds);
+        let index = self.objects.insert(LooseObject { bounds, data, node });
+        self.nodes.get_mut(node).unwrap().objects.push(index);
+
+        Ok(index)
+    }
+
+    // Moves the object to the node matching its new bounds
+    pub fn update(
+        &mut self,
+        index: Index,
+        bounds: impl Into<Bounds>,
+    ) -> Result<(), SpatialIndexError> {
+        let bounds = bounds.into();
+
+        if !bounds.is_finite() {
+            let (min, _) = bounds.aabb();
+            return Err(SpatialIndexError::OutOfBounds(min));
+        }
+
+        let old_node = match self.objects.get(index) {
+            Some(object) => object.node,
+            None => return Ok(()),
+        };
+
+        let node = self.node_for(&bounds);
+        let object = self.objects.get_mut(index).unwrap();
+        object.bounds = bounds;
+
+        if node != old_node {
+            object.node = node;
+            self.nodes.get_mut(node).unwrap().objects.push(index);
+            self.detach(old_node, index);
+        }
+
+        Ok(())
+    }
+
+    pub fn remove(&mut self, index: Index) -> Option<T> {
+        let object = self.objects.remove(index)?;
+        self.detach(object.node, index);
+
+        Some(object.data)
+    }
+
+    // Removes every object and node except the root
+    pub fn clear(&mut self) {
+        let root = self.root;
+
+        self.nodes.retain(|index, _| index == root);
+        self.objects.clear();
+
+        let root = self.nodes.get_mut(root).unwrap();
+        root.children = [None; 8];
+        root.objects.clear();
+    }
+
+    pub fn depth(&self, node_index: Index) -> usize {
+        let mut depth = 0;
+        let mut node = self.nodes.get(node_index).unwrap();
+
+        while let Some(parent) = node.parent {
+            depth += 1;
+            node = self.nodes.get(parent).unwrap();
+        }
+
+        depth
+    }
+
+    // Loose bounds of the node
+    pub fn loose_bounds(&self, node_index: Index) -> (Point, Point) {
+        let node = self.nodes.get(node_index).unwrap();
+
+        Bounds::Sphere {
+            center: node.center.clone(),
+            radius: node.half_size * self.config.looseness,
+        }
+        .aabb()
+    }
+
+    fn fits(&self, center: &Point, half_size: f32, min: &Point, max: &Point) -> bool {
+        let extent = half_size * self.config.looseness;
+
+        min.x >= center.x - extent
+            && max.x <= center.x + extent
+            && min.y >= center.y - extent
+            && max.y <= center.y + extent
+            && min.z >= center.z - extent
+            && max.z <= center.z + extent
+    }
+
+    // Deepest node whose loose bounds contain the bounds, creating missing nodes on the way.
+    // Only the child on the side of the center of the bounds is tried at every level.
+    fn node_for(&mut self, bounds: &Bounds) -> Index {
+        let (min, max) = bounds.aabb();
+        let center = Point {
+            x: (min.x + max.x) / 2.0,
+            y: (min.y + max.y) / 2.0,
+            z: (min.z + max.z) / 2.0,
+        };
+
+        let mut node_index = self.root;
+
+        for _ in 0..self.config.max_depth {
+            let node = self.nodes.get(node_index).unwrap();
+            let octant = octant(&node.center, &center);
+            let half_size = node.half_size / 2.0;
+            let child_center = child_center(&node.center, octant, half_size);
+
+            if !self.fits(&child_center, half_size, &min, &max) {
+                break;
+            }
+
+            node_index = match node.children[octant] {
+                Some(child) => child,
+                None => {
+                    let child = self.nodes.insert(LooseNode {
+                        center: child_center,
+                        half_size,
+                        parent: Some(node_index),
+                        children: [None; 8],
+                        objects: vec![],
+                    });
+
+                    self.nodes.get_mut(node_index).unwrap().children[octant] = Some(child);
+
+                    child
+                }
+            };
+        }
+
+        node_index
+    }
+
+    // Removes the object from the node and drops nodes that became empty
+    fn detach(&mut self, node_index: Index, index: Index) {
+        self.nodes
+            .get_mut(node_index)
+            .unwrap()
+            .objects
+            .retain(|object| *object != index);
+
+        let mut current = node_index;
+
+        while current != self.root {
+            let node = self.nodes.get(current).unwrap();
+
+            if !node.objects.is_empty() || node.children.iter().any(Option::is_some) {
+                break;
+            }
+
+            let parent = node.parent.unwrap();
+            self.nodes.remove(current);
+
+            let parent_node = self.nodes.get_mut(parent).unwrap();
+            let slot = parent_node
+                .children
+                .iter()
+                .position(|child| *child == Some(current))
+                .unwrap();
+            parent_node.children[slot] = None;
+
+            current = parent;
+        }
+    }
+
+    // Calls visit for every object whose bounds overlap the given bounds
+    pub fn for_each_overlapping<F>(&self, bounds: &Bounds, mut visit: F)
+    where
+        F: FnMut(&Bounds, &T),
+    {
+        let (min, max) = bounds.aabb();
+
+        self.visit_overlapping(self.root, bounds, &min, &max, &mut visit);
+    }
+
+    // Returns all objects whose bounds overlap the given bounds
+    pub fn find_overlapping(&self, bounds: &Bounds) -> Vec<&T> {
+        let mut found = vec![];
+        let (min, max) = bounds.aabb();
+
+        self.visit_overlapping(self.root, bounds, &min, &max, &mut |_, data| {
+            found.push(data)
+        });
+
+        found
+    }
+
+    fn visit_overlapping<'a, F>(
+        &'a self,
+        node_index: Index,
+        bounds: &Bounds,
+        min: &Point,
+        max: &Point,
+        visit: &mut F,
+    ) where
+        F: FnMut(&'a Bounds, &'a T),
+    {
+        let node = self.nodes.get(node_index).unwrap();
+
+        // The root also holds objects outside its loose bounds, so it is always visited
+        for index in node.objects.iter() {
+            let object = self.objects.get(*index).unwrap();
+
+            if object.bounds.intersects(bounds) {
+                visit(&object.bounds, &object.data);
+            }
+        }
+
+        for child in node.children.iter().flatten() {
+            let (child_min, child_max) = self.loose_bounds(*child);
+
+            if boxes_overlap(&child_min, &child_max, min, max) {
+                self.visit_overlapping(*child, bounds, min, max, visit);
+            }
+        }
+    }
+
+    // Returns the k objects closest to the point (distance to their bounds) sorted by distance
+    pub fn k_nearest(&self, point: impl ToPoint, k: usize) -> Vec<&T> {
+        let point = &point.to_point();
+        let mut nearest: BinaryHeap<QueueItem> = BinaryHeap::with_capacity(k + 1);
+
+        if k > 0 {
+            let mut queue = BinaryHeap::new();
+            queue.push(Reverse(QueueItem {
+                distance: 0.0,
+                index: self.root,
+            }));
+
+            while let Some(Reverse(item)) = queue.pop() {
+                // Loose bounds contain everything below the node, so nothing closer is left
+                if nearest.len() == k && item.distance > nearest.peek().unwrap().distance {
+                    break;
+                }
+
+                let node = self.nodes.get(item.index).unwrap();
+
+                for index in node.objects.iter() {
+                    let distance = self
+                        .objects
+                        .get(*index)
+                        .unwrap()
+                        .bounds
+                        .distance_squared(point);
+
+                    if nearest.len() < k {
+                        nearest.push(QueueItem {
+                            distance,
+                            index: *index,
+                        });
+                    } else if distance < nearest.peek().unwrap().distance {
+                        nearest.pop();
+                        nearest.push(QueueItem {
+                            distance,
+                            index: *index,
+                        });
+                    }
+                }
+
+                for child in node.children.iter().flatten() {
+                    let (min, max) = self.loose_bounds(*child);
+
+                    queue.push(Reverse(QueueItem {
+                        distance: box_distance_squared(&min, &max, point),
+                        index: *child,
+                    }));
+                }
+            }
+        }
+
+        nearest
+            .into_sorted_vec()
+            .iter()
+            .map(|item| &self.objects.get(item.index).unwrap().data)
+            .collect()
+    }
+}
+
+// Points are stored as spheres with radius 0
+impl<T: Send + Sync> SpatialIndex<T> for LooseOctree<T> {
+    fn build(points: Vec<(Point, T)>, config: &IndexConfig) -> Result<Self, SpatialIndexError> {
+        let (mut min, mut max) = (
+            Point {
+                x: f32::MAX,
+                y: f32::MAX,
+                z: f32::MAX,
+            },
+            Point {
+                x: f32::MIN,
+                y: f32::MIN,
+                z: f32::MIN,
+            },
+        );
+
+        for (point, _) in points.iter().filter(|(point, _)| point.is_finite()) {
+            min.x = min.x.min(point.x);
+            min.y = min.y.min(point.y);
+            min.z = min.z.min(point.z);
+            max.x = max.x.max(point.x);
+            max.y = max.y.max(point.y);
+            max.z = max.z.max(point.z);
+        }
+
+        let (center, half_size) = if min.x <= max.x {
+            (
+                Point {
+                    x: (min.x + max.x) / 2.0,
+                    y: (min.y + max.y) / 2.0,
+                    z: (min.z + max.z) / 2.0,
+                },
+                (max.x - min.x).max(max.y - min.y).max(max.z - min.z) / 2.0,
+            )
+        } else {
+            (Point::ZERO, 0.0)
+        };
+
+        let mut octree = Self::new(
+            center,
+            half_size,
+            LooseOctreeConfig {
+                max_depth: config.octree.max_depth,
+                ..Default::default()
+            },
+        );
+
+        for (point, data) in points {
+            octree.insert(point, data)?;
+        }
+
+        Ok(octree)
+    }
+
+    fn for_each_point_within_radius(
+        &self,
+        point: &Point,
+        radius: f32,
+        visit: &mut dyn FnMut(&Point, &T),
+    ) {
+        let bounds = Bounds::sphere(point, radius);
+
+        self.for_each_overlapping(&bounds, |bounds, data| visit(&bounds.center(), data));
+    }
+
+    fn k_nearest(&self, point: &Point, k: usize) -> Vec<&T> {
+        LooseOctree::k_nearest(self, point, k)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::Rng;
+
+    use super::*;
+    use crate::test_utils::{random_index, random_point, test_rng, SIZE};
+
+    // Spheres and boxes of very different sizes, some reaching outside the root
+    fn random_bounds(rng: &mut impl Rng) -> Bounds {
+        let center = random_point(rng);
+        let size = rng.gen::<f32>().powi(4) * SIZE;
+
+        if rng.gen::<bool>() {
+            Bounds::sphere(center, size)
+        } else {
+            let min = Point {
+                x: center.x - size * rng.gen::<f32>(),
+                y: center.y - size * rng.gen::<f32>(),
+                z: center.z - size * rng.gen::<f32>(),
+            };
+
+            Bounds::cuboid(min, center)
+        }
+    }
+
+    fn random_octree(
+        rng: &mut impl Rng,
+        count: usize,
+    ) -> (LooseOctree<usize>, Vec<Index>, Vec<Bounds>) {
+        let ((octree, indices), bounds) = random_index(rng, count, random_bounds, |bounds| {
+            let mut octree = LooseOctree::new(Point::ZERO, SIZE, LooseOctreeConfig::default());
+            let indices = bounds
+                .into_iter()
+                .map(|(bounds, i)| octree.insert(bounds, i).unwrap())
+                .collect();
+
+            (octree, indices)
+        });
+
+        (octree, indices, bounds)
+    }
+
+    // Every object is in the deepest node whose loose bounds contain it
+    fn assert_objects_placed_deepest<T>(octree: &LooseOctree<T>) {
+        for (index, object) in octree.objects.iter() {
+            let node = octree.nodes.get(object.node).unwrap();
+            let (min, max) = object.bounds.aabb();
+
+            assert!(node.objects.contains(&index));
+
+            if object.node != octree.root {
+                assert!(octree.fits(&node.center, node.half_size, &min, &max));
+            }
+
+            if octree.depth(object.node) < octree.config.max_depth {
+                let center = Point {
+                    x: (min.x + max.x) / 2.0,
+                    y: (min.y + max.y) / 2.0,
+                    z: (min.z + max.z) / 2.0,
+                };
+                let octant = octant(&node.center, &center);
+                let half_size = node.half_size / 2.0;
+                let child_center = child_center(&node.center, octant, half_size);
+
+                assert!(!octree.fits(&child_center, half_size, &min, &max));
+            }
+        }
+    }
+
+    fn assert_overlaps_match(octree: &LooseOctree<usize>, bounds: &[Option<Bounds>]) {
+        let mut rng = test_rng();
+        for _ in 0..50 {
+            let query = random_bounds(&mut rng);
+
+            let expected = (0..bounds.len())
+                .filter(|i| {
+                    bounds[*i]
+                        .as_ref()
+                        .map_or(false, |bounds| bounds.intersects(&query))
+                })
+                .collect::<Vec<_>>();
+
+            let mut found = octree
+                .find_overlapping(&query)
+                .into_iter()
+                .copied()
+                .collect::<Vec<_>>();
+            found.sort();
+
+            assert_eq!(found, expected);
+
+            let center = random_point(&mut rng);
+            let mut expected = bounds
+                .iter()
+                .flatten()
+                .map(|bounds| bounds.distance_squared(&center))
+                .collect::<Vec<_>>();
+            expected.sort_by(|a, b| a.total_cmp(b));
+            expected.truncate(10);
+
+            let found = octree
+                .k_nearest(&center, 10)
+                .into_iter()
+                .map(|i| bounds[*i].as_ref().unwrap().distance_squared(&center))
+                .collect::<Vec<_>>();
+
+            assert_eq!(found, expected);
+        }
+    }
+
+    #[test]
+    fn overlap_queries_match_brute_force() {
+        let mut rng = test_rng();
+        let (octree, _, bounds) = random_octree(&mut rng, 2000);
+
+        assert_objects_placed_deepest(&octree);
+        assert_overlaps_match(&octree, &bounds.into_iter().map(Some).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn updates_and_removals_keep_objects_placed() {
+        let mut rng = test_rng();
+        let (mut octree, indices, bounds) = random_octree(&mut rng, 2000);
+        let mut bounds = bounds.into_iter().map(Some).collect::<Vec<_>>();
+
+        for (i, index) in indices.iter().enumerate() {
+            if i % 3 == 0 {
+                assert_eq!(octree.remove(*index), Some(i));
+                bounds[i] = None;
+            } else if i % 3 == 1 {
+                let new_bounds = random_bounds(&mut rng);
+                octree.update(*index, new_bounds.clone()).unwrap();
+                bounds[i] = Some(new_bounds);
+            }
+        }
+
+        assert_objects_placed_deepest(&octree);
+        assert_overlaps_match(&octree, &bounds);
+
+        // Emptied nodes are removed
+        for (index, node) in octree.nodes.iter() {
+            assert!(
+                index == octree.root
+                    || !node.objects.is_empty()
+                    || node.children.iter().any(Option::is_some)
+            );
+        }
+
+        octree.clear();
+
+        assert!(octree.is_empty());
+        assert_eq!(octree.nodes.len(), 1);
+    }
+
+    #[test]
+    fn vector_types_give_same_results() {
+        let mut rng = test_rng();
+        let points = (0..500).map(|_| random_point(&mut rng)).collect::<Vec<_>>();
+        let config = LooseOctreeConfig::default();
+
+        let mut from_points = LooseOctree::new(Point::ZERO, SIZE, config.clone());
+        let mut from_arrays = LooseOctree::new([0.0; 3], SIZE, config.clone());
+        let mut from_vectors = LooseOctree::new(nalgebra::Vector3::zeros(), SIZE, config);
+
+        for (i, point) in points.iter().enumerate() {
+            let array = [point.x, point.y, point.z];
+            let vector = nalgebra::Vector3::new(point.x, point.y, point.z);
+
+            if i % 2 == 0 {
+                from_points.insert(point, i).unwrap();
+                from_arrays.insert(array, i).unwrap();
+                from_vectors.insert(vector, i).unwrap();
+            } else {
+                let max = [point.x + 1.0, point.y + 1.0, point.z + 1.0];
+
+                from_points.insert(Bounds::cuboid(point, max), i).unwrap();
+                from_arrays.insert(Bounds::cuboid(array, max), i).unwrap();
+                from_vectors
+                    .insert(Bounds::cuboid(vector, nalgebra::Vector3::from(max)), i)
+                    .unwrap();
+            }
+        }
+
+        fn sorted(mut found: Vec<&usize>) -> Vec<&usize> {
+            found.sort();
+            found
+        }
+
+        for _ in 0..20 {
+            let center = random_point(&mut rng);
+            let array = [center.x, center.y, center.z];
+            let vector = nalgebra::Vector3::new(center.x, center.y, center.z);
+
+            let expected =
+                sorted(from_points.find_overlapping(&Bounds::sphere(&center, SIZE / 2.0)));
+            assert_eq!(
+                sorted(from_arrays.find_overlapping(&Bounds::sphere(array, SIZE / 2.0))),
+                expected
+            );
+            assert_eq!(
+                sorted(from_vectors.find_overlapping(&Bounds::sphere(vector, SIZE / 2.0))),
+                expected
+            );
+
+            let expected = from_points.k_nearest(&center, 10);
+            assert_eq!(from_arrays.k_nearest(array, 10), expected);
+            assert_eq!(from_vectors.k_nearest(vector, 10), expected);
+        }
+    }
+}
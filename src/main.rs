@@ -1,30 +1,39 @@
-mod octree;
+#[cfg(feature = "octree")]
+mod octree_overlay;
 mod target;
 mod vehicle;
 
+use std::env;
+
+use autonomous_characters_3d::simulation::SimulationParams;
+#[cfg(feature = "ui")]
+use autonomous_characters_3d::{integrator::Integrator, spatial_index::SpatialBackend};
 use bevy::{
     diagnostic::{Diagnostics, FrameTimeDiagnosticsPlugin},
     prelude::*,
 };
+#[cfg(feature = "picking")]
 use bevy_mod_picking::*;
-use octree::*;
+#[cfg(feature = "octree")]
+use octree_overlay::OverlayColoring;
 use smooth_bevy_cameras::{
     controllers::orbit::{OrbitCameraBundle, OrbitCameraController, OrbitCameraPlugin},
     LookTransformPlugin,
 };
 
+#[cfg(feature = "ui")]
 use bevy_egui::{egui, EguiContext, EguiPlugin};
 
 fn main() {
-    App::new()
-        .insert_resource(ClearColor(Color::rgb(0.2, 0.2, 0.2)))
+    let mut app = App::new();
+
+    app.insert_resource(ClearColor(Color::rgb(0.2, 0.2, 0.2)))
         .insert_resource(Msaa { samples: 4 })
         .init_resource::<GlobalState>()
         .init_resource::<RenderState>()
         .add_startup_system_to_stage(StartupStage::PreStartup, load_assets)
         .add_startup_system(setup_scene)
         .add_startup_system(configure_global_state)
-        // .add_startup_system(test_octree_visualization)
         // .add_system(bevy::window::close_on_esc)
         .add_plugins(DefaultPlugins.set(WindowPlugin {
             window: WindowDescriptor {
@@ -37,81 +46,24 @@ fn main() {
             },
             ..default()
         }))
-        .add_plugins(DefaultPickingPlugins)
         .add_plugin(LookTransformPlugin)
         .add_plugin(OrbitCameraPlugin::default())
-        .add_plugin(EguiPlugin)
         .add_plugin(FrameTimeDiagnosticsPlugin::default())
         .add_plugin(vehicle::VehiclePlugin)
         .add_plugin(target::TargetPlugin)
-        .add_system(fps_update_system)
-        .add_system(ui)
-        .run();
-}
-
-pub fn test_octree_visualization(
-    mut commands: Commands,
-    mut meshes: ResMut<Assets<Mesh>>,
-    mut materials: ResMut<Assets<StandardMaterial>>,
-) {
-    let mut octree: Octree<i32> = Octree::new(100);
-    octree.create_root(
-        Point {
-            x: 0.0,
-            y: 0.0,
-            z: 0.0,
-        },
-        Point {
-            x: -50.0,
-            y: -50.0,
-            z: -50.0,
-        },
-        Point {
-            x: 50.0,
-            y: 50.0,
-            z: 50.0,
-        },
-    );
-
-    // Insert points
-    for i in 0..50000 {
-        let point = Point {
-            x: -50.0 + rand::random::<f32>() * 100.0,
-            y: -50.0 + rand::random::<f32>() * 100.0,
-            z: -50.0 + rand::random::<f32>() * 100.0,
-        };
-
-        octree.insert(point, i);
-    }
+        .add_system(fps_update_system);
 
-    // Print nodes and their points
-    for (_, node) in octree.nodes.iter() {
-        println!("Bounds: {:?}", node.bounds);
+    // Selecting the target with the mouse
+    #[cfg(feature = "picking")]
+    app.add_plugins(DefaultPickingPlugins);
 
-        for point_index in node.points.iter() {
-            let point = octree.points.get(*point_index).unwrap();
-            println!("Point: {:?}", point.point);
-        }
-    }
+    #[cfg(feature = "ui")]
+    app.add_plugin(EguiPlugin).add_system(ui);
 
-    // Draw nodes
-    for (_, node) in octree.nodes.iter() {
-        if node.children.len() > 0 {
-            continue;
-        }
-        let (center, min, max) = &node.bounds;
+    #[cfg(feature = "octree")]
+    app.add_plugin(octree_overlay::OctreeOverlayPlugin);
 
-        let mesh = meshes.add(Mesh::from(shape::Cube {
-            size: 8.0 * (max.x - min.x) / 10.0,
-        }));
-
-        commands.spawn(PbrBundle {
-            mesh,
-            material: materials.add(Color::rgb(0.0, 0.0, 1.0).into()),
-            transform: Transform::from_translation(Vec3::new(center.x, center.y, center.z)),
-            ..Default::default()
-        });
-    }
+    app.run();
 }
 
 fn setup_scene(mut commands: Commands, asset_server: Res<AssetServer>) {
@@ -152,49 +104,42 @@ fn setup_scene(mut commands: Commands, asset_server: Res<AssetServer>) {
     ));
 
     // ORBIT CAMERA
-    commands
-        .spawn(Camera3dBundle::default())
-        .insert(OrbitCameraBundle::new(
-            OrbitCameraController {
-                mouse_rotate_sensitivity: Vec2 { x: 0.5, y: 0.5 },
-                mouse_translate_sensitivity: Vec2 { x: 0.5, y: 0.5 },
-                ..Default::default()
-            },
-            Vec3::new(-2.0, 5.0, 5.0),
-            Vec3::new(0., 0., 0.),
-        ))
-        .insert(PickingCameraBundle::default());
+    let mut camera = commands.spawn(Camera3dBundle::default());
+    camera.insert(OrbitCameraBundle::new(
+        OrbitCameraController {
+            mouse_rotate_sensitivity: Vec2 { x: 0.5, y: 0.5 },
+            mouse_translate_sensitivity: Vec2 { x: 0.5, y: 0.5 },
+            ..Default::default()
+        },
+        Vec3::new(-2.0, 5.0, 5.0),
+        Vec3::new(0., 0., 0.),
+    ));
+
+    #[cfg(feature = "picking")]
+    camera.insert(PickingCameraBundle::default());
 }
 
 #[derive(Default, Resource)]
 struct GlobalState {
-    // Basic info
     vehicle_count: usize,
-    vehicle_size: f32,
-    vehicle_mass: f32,
-    vehicle_wander_speed: f32,
-
-    // Limits
-    vehicle_max_speed: f32,
-
-    // Factors
-    vehicle_seperation_factor: f32,
-    vehicle_alignment_factor: f32,
-    vehicle_cohesion_factor: f32,
-    vehicle_wander_factor: f32,
-    vehicle_wall_avoid_factor: f32,
-    vehicle_seek_factor: f32,
-
-    // Distances
-    vehicle_seperation_distance: f32,
-    vehicle_alignment_distance: f32,
-    vehicle_cohesion_distance: f32,
-    vehicle_wander_distance: f32,
-    vehicle_wander_radius: f32,
-
-    // Toggle mode
-    use_octree: bool,
-    octree_size: usize,
+
+    // Boids algorithm and spatial index, passed to the simulation every frame
+    params: SimulationParams,
+
+    // Octree wireframe
+    #[cfg(feature = "octree")]
+    show_octree: bool,
+    #[cfg(feature = "octree")]
+    octree_overlay_coloring: OverlayColoring,
+    #[cfg(feature = "octree")]
+    octree_overlay_selected_only: bool,
+    // Simulation id of the vehicle whose leaf is drawn, chosen in the menu
+    #[cfg(feature = "octree")]
+    selected_vehicle: Option<usize>,
+
+    // Despawn all vehicles and spawn them again from the seed
+    restart_simulation: bool,
+
     benchmark_mode: bool,
     benchmark_step: usize,
     benchmark_results: Vec<f32>,
@@ -203,28 +148,21 @@ struct GlobalState {
 
 fn configure_global_state(mut state: ResMut<GlobalState>) {
     state.vehicle_count = 100;
-    state.vehicle_size = 1.0;
-    state.vehicle_mass = 60.0;
-    state.vehicle_wander_speed = 40.0;
-
-    state.vehicle_max_speed = 80.0;
-
-    state.vehicle_seperation_factor = 1.5;
-    state.vehicle_alignment_factor = 3.0;
-    state.vehicle_cohesion_factor = 4.0;
-    state.vehicle_wander_factor = 1.0;
-    state.vehicle_wall_avoid_factor = 1.5;
-    state.vehicle_seek_factor = 7.0;
-
-    state.vehicle_seperation_distance = 4.0;
-    state.vehicle_alignment_distance = 30.0;
-    state.vehicle_cohesion_distance = 20.0;
-
-    state.vehicle_wander_distance = 4.0;
-    state.vehicle_wander_radius = 1.5;
-
-    state.use_octree = false;
-    state.octree_size = 100;
+    state.params = SimulationParams::default();
+    // The first argument is the seed, runs with the same seed are the same
+    state.params.seed = env::args()
+        .nth(1)
+        .and_then(|seed| seed.parse().ok())
+        .unwrap_or(state.params.seed);
+    state.restart_simulation = false;
+
+    #[cfg(feature = "octree")]
+    {
+        state.show_octree = false;
+        state.octree_overlay_coloring = OverlayColoring::Depth;
+        state.octree_overlay_selected_only = false;
+        state.selected_vehicle = None;
+    }
 
     state.benchmark_mode = false;
     state.benchmark_step = 0;
@@ -256,90 +194,248 @@ fn load_assets(
     render_state.vehicle_scene = asset_server.load("cone.glb#Scene0");
 }
 
-fn ui(mut egui_context: ResMut<EguiContext>, mut state: ResMut<GlobalState>) {
+#[cfg(feature = "ui")]
+fn ui(
+    mut egui_context: ResMut<EguiContext>,
+    mut state: ResMut<GlobalState>,
+    #[cfg(feature = "octree")] octree_stats: Res<vehicle::VehicleOctreeStats>,
+) {
     egui::Window::new("Menu")
         .default_size([300.0, 100.0])
         .show(egui_context.ctx_mut(), |ui| {
             ui.label("Vehicle count");
             ui.add(egui::Slider::new(&mut state.vehicle_count, 0..=50000).text("count"));
             ui.label("Vehicle mass");
-            ui.add(egui::Slider::new(&mut state.vehicle_mass, 1.0..=1000.0).text("mass"));
+            ui.add(egui::Slider::new(&mut state.params.vehicle_mass, 1.0..=1000.0).text("mass"));
             ui.label("Vehicle max speed");
-            ui.add(egui::Slider::new(&mut state.vehicle_max_speed, 0.1..=100.0).text("max speed"));
+            ui.add(
+                egui::Slider::new(&mut state.params.vehicle_max_speed, 0.1..=100.0)
+                    .text("max speed"),
+            );
             ui.label("Vehicle wander speed");
             ui.add(
-                egui::Slider::new(&mut state.vehicle_wander_speed, 0.1..=100.0)
+                egui::Slider::new(&mut state.params.vehicle_wander_speed, 0.1..=100.0)
                     .text("wander speed"),
             );
             ui.label("Vehicle seperation factor");
             ui.add(
-                egui::Slider::new(&mut state.vehicle_seperation_factor, 0.1..=10.0)
+                egui::Slider::new(&mut state.params.vehicle_seperation_factor, 0.1..=10.0)
                     .text("separation")
                     .step_by(0.1),
             );
             ui.label("Vehicle alignment factor");
             ui.add(
-                egui::Slider::new(&mut state.vehicle_alignment_factor, 0.1..=10.0)
+                egui::Slider::new(&mut state.params.vehicle_alignment_factor, 0.1..=10.0)
                     .text("alignment")
                     .step_by(0.1),
             );
             ui.label("Vehicle cohesion factor");
             ui.add(
-                egui::Slider::new(&mut state.vehicle_cohesion_factor, 0.1..=10.0)
+                egui::Slider::new(&mut state.params.vehicle_cohesion_factor, 0.1..=10.0)
                     .text("cohesion")
                     .step_by(0.1),
             );
             ui.label("Vehicle wander factor");
             ui.add(
-                egui::Slider::new(&mut state.vehicle_wander_factor, 0.1..=10.0)
+                egui::Slider::new(&mut state.params.vehicle_wander_factor, 0.1..=10.0)
                     .text("wander")
                     .step_by(0.1),
             );
             ui.label("Vehicle wall avoid factor");
             ui.add(
-                egui::Slider::new(&mut state.vehicle_wall_avoid_factor, 0.1..=10.0)
+                egui::Slider::new(&mut state.params.vehicle_wall_avoid_factor, 0.1..=10.0)
                     .text("wall avoid")
                     .step_by(0.1),
             );
 
             ui.label("Vehicle seperation distance");
             ui.add(
-                egui::Slider::new(&mut state.vehicle_seperation_distance, 1.0..=100.0)
+                egui::Slider::new(&mut state.params.vehicle_seperation_distance, 1.0..=100.0)
                     .text("separation")
                     .step_by(1.0),
             );
             ui.label("Vehicle alignment distance");
             ui.add(
-                egui::Slider::new(&mut state.vehicle_alignment_distance, 1.0..=100.0)
+                egui::Slider::new(&mut state.params.vehicle_alignment_distance, 1.0..=100.0)
                     .text("alignment")
                     .step_by(1.0),
             );
             ui.label("Vehicle cohesion distance");
             ui.add(
-                egui::Slider::new(&mut state.vehicle_cohesion_distance, 1.0..=100.0)
+                egui::Slider::new(&mut state.params.vehicle_cohesion_distance, 1.0..=1000.0)
                     .text("cohesion")
+                    .logarithmic(true)
                     .step_by(1.0),
             );
             ui.label("Vehicle wander distance");
             ui.add(
-                egui::Slider::new(&mut state.vehicle_wander_distance, 1.0..=100.0)
+                egui::Slider::new(&mut state.params.vehicle_wander_distance, 1.0..=100.0)
                     .text("wander")
                     .step_by(1.0),
             );
             ui.label("Vehicle wander radius");
             ui.add(
-                egui::Slider::new(&mut state.vehicle_wander_radius, 1.0..=100.0)
+                egui::Slider::new(&mut state.params.vehicle_wander_radius, 1.0..=100.0)
                     .text("wander")
                     .step_by(1.0),
             );
 
             ui.separator();
-            ui.checkbox(&mut state.use_octree, "Use octree");
+            egui::ComboBox::from_label("Integrator")
+                .selected_text(state.params.integrator.name())
+                .show_ui(ui, |ui| {
+                    for integrator in Integrator::ALL {
+                        ui.selectable_value(
+                            &mut state.params.integrator,
+                            integrator,
+                            integrator.name(),
+                        );
+                    }
+                });
+            ui.add(
+                egui::Slider::new(&mut state.params.fixed_timestep, 1.0 / 240.0..=1.0 / 10.0)
+                    .text("timestep (s)")
+                    .logarithmic(true),
+            );
+            ui.horizontal(|ui| {
+                ui.add(egui::DragValue::new(&mut state.params.seed).prefix("seed "));
+
+                if ui.button("Restart").clicked() {
+                    state.restart_simulation = true;
+                }
+            });
+
+            ui.separator();
+            ui.checkbox(
+                &mut state.params.use_topological,
+                "Topological neighbors (k)",
+            );
             ui.add(
-                egui::Slider::new(&mut state.octree_size, 2..=500)
+                egui::Slider::new(&mut state.params.topological_neighbors, 1..=50)
+                    .text("k")
+                    .step_by(1.0),
+            );
+
+            ui.checkbox(
+                &mut state.params.use_barnes_hut,
+                "Summarize far vehicles (Barnes-Hut, octree only)",
+            );
+            ui.add(
+                egui::Slider::new(&mut state.params.barnes_hut_theta, 0.0..=2.0)
+                    .text("opening angle"),
+            );
+
+            ui.checkbox(
+                &mut state.params.periodic_world,
+                "Wrap around world edges (radius neighbors only)",
+            );
+
+            ui.separator();
+            egui::ComboBox::from_label("Spatial index")
+                .selected_text(state.params.spatial_index.name())
+                .show_ui(ui, |ui| {
+                    for backend in SpatialBackend::ALL {
+                        ui.selectable_value(
+                            &mut state.params.spatial_index,
+                            backend,
+                            backend.name(),
+                        );
+                    }
+                });
+            ui.checkbox(
+                &mut state.params.octree_rebuild,
+                "Rebuild octree every frame",
+            );
+            ui.add(
+                egui::Slider::new(&mut state.params.octree_size, 2..=500)
                     .text("octree size")
                     .step_by(1.0),
             );
+            ui.add(
+                egui::Slider::new(&mut state.params.octree_max_depth, 1..=20)
+                    .text("octree max depth")
+                    .step_by(1.0),
+            );
+            ui.add(
+                egui::Slider::new(&mut state.params.octree_min_cell_size, 0.1..=100.0)
+                    .text("octree min cell size"),
+            );
+            #[cfg(feature = "octree")]
+            egui::CollapsingHeader::new("Octree statistics").show(ui, |ui| {
+                let stats = match &octree_stats.0 {
+                    Some(stats) => stats,
+                    None => {
+                        ui.label("Select the octree as the spatial index");
+                        return;
+                    }
+                };
+
+                ui.label(format!("Depth: {}", stats.depth));
+                ui.label(format!("Nodes: {} ({} leaves)", stats.nodes, stats.leaves));
+                ui.label(format!(
+                    "Empty leaves: {} ({:.1}%)",
+                    stats.empty_leaves(),
+                    stats.empty_leaf_ratio() * 100.0
+                ));
+                ui.label(format!(
+                    "Memory: {:.1} KiB",
+                    stats.bytes_held as f32 / 1024.0
+                ));
+
+                // Histogram with at most 8 bars
+                ui.label("Points per leaf");
+                let width = ((stats.points_per_leaf.len() + 7) / 8).max(1);
+
+                for (i, leaves) in stats.points_per_leaf.chunks(width).enumerate() {
+                    let leaves = leaves.iter().sum::<usize>();
+                    let (first, last) = (i * width, (i + 1) * width - 1);
+
+                    ui.add(
+                        egui::ProgressBar::new(leaves as f32 / stats.leaves.max(1) as f32)
+                            .text(format!("{first}-{last}: {leaves}")),
+                    );
+                }
+            });
+
+            #[cfg(feature = "octree")]
+            {
+                ui.separator();
+                ui.checkbox(&mut state.show_octree, "Show octree wireframe");
+                egui::ComboBox::from_label("Wireframe color")
+                    .selected_text(state.octree_overlay_coloring.name())
+                    .show_ui(ui, |ui| {
+                        for coloring in OverlayColoring::ALL {
+                            ui.selectable_value(
+                                &mut state.octree_overlay_coloring,
+                                coloring,
+                                coloring.name(),
+                            );
+                        }
+                    });
+                ui.checkbox(
+                    &mut state.octree_overlay_selected_only,
+                    "Only the leaf of the selected vehicle",
+                );
+                ui.horizontal(|ui| {
+                    let mut id = state.selected_vehicle.unwrap_or_default();
+                    let last = state.vehicle_count.saturating_sub(1);
+
+                    if ui
+                        .add(
+                            egui::DragValue::new(&mut id)
+                                .clamp_range(0..=last)
+                                .prefix("vehicle "),
+                        )
+                        .changed()
+                    {
+                        state.selected_vehicle = Some(id);
+                    }
+
+                    if ui.button("Deselect").clicked() {
+                        state.selected_vehicle = None;
+                    }
+                });
+            }
 
             ui.separator();
             ui.checkbox(&mut state.benchmark_mode, "Benchmark mode");
@@ -1,134 +1,1962 @@
+use std::{
+    cmp::{Ordering, Reverse},
+    collections::BinaryHeap,
+};
+
 use generational_arena::{Arena, Index};
+use rayon::prelude::*;
+
+use crate::spatial_index::{IndexConfig, SpatialIndex};
+pub use crate::{
+    point::{Point, ToPoint},
+    spatial_index::SpatialIndexError,
+};
+
+// Subtrees with fewer points are built on the current thread
+const PARALLEL_BUILD_THRESHOLD: usize = 1024;
+
+// Edge of the smallest root created by build_from or grow, so a root never has zero width
+const MIN_ROOT_SIZE: f32 = 1.0;
+
+// Bits per axis in a Morton code
+pub const MORTON_BITS: usize = 21;
 
 // Octree struct usng arena allocator
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Octree<T> {
-    pub max_points: usize,
+    pub config: OctreeConfig,
     pub root: Option<Index>,
+    // Leaf without bounds holding points outside the root (OutOfBoundsPolicy::Overflow)
+    pub overflow: Option<Index>,
     pub nodes: Arena<OctreeNode>,
     pub points: Arena<PointWrapper<T>>,
+    // Emptied node vectors kept for reuse by new nodes
+    #[cfg_attr(feature = "serde", serde(skip))]
+    spare_points: Vec<Vec<Index>>,
+    #[cfg_attr(feature = "serde", serde(skip))]
+    spare_children: Vec<Vec<Index>>,
+    // Number of times the storage had to grow
+    #[cfg_attr(feature = "serde", serde(skip))]
+    allocations: usize,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct OctreeConfig {
+    // Leaves with more points are subdivided
+    pub max_points: usize,
+    // Leaves at this depth (root is 0) are never subdivided and can exceed max_points
+    pub max_depth: usize,
+    // Leaves are not subdivided if a child edge would be shorter than this
+    pub min_cell_size: f32,
+    pub out_of_bounds: OutOfBoundsPolicy,
+}
+
+impl Default for OctreeConfig {
+    fn default() -> Self {
+        Self {
+            max_points: 100,
+            max_depth: 16,
+            min_cell_size: 0.0,
+            out_of_bounds: OutOfBoundsPolicy::Overflow,
+        }
+    }
+}
+
+// What happens to points outside the root bounds
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum OutOfBoundsPolicy {
+    // Grow the root outward until it contains the point
+    Grow,
+    // Store the point in an overflow bucket that every query scans
+    Overflow,
+    // Refuse the point with SpatialIndexError::OutOfBounds
+    Reject,
 }
 
 #[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct PointWrapper<T> {
     pub point: Point,
     pub data: T,
+    // Leaf node that currently holds the point
+    pub leaf: Option<Index>,
 }
 
+// Plane with points where normal.dot(point) + distance >= 0 on the inside
 #[derive(Debug, Clone)]
-pub struct Point {
-    pub x: f32,
-    pub y: f32,
-    pub z: f32,
+pub struct Plane {
+    pub normal: Point,
+    pub distance: f32,
+}
+
+impl Plane {
+    pub fn signed_distance(&self, point: &Point) -> f32 {
+        self.normal.x * point.x + self.normal.y * point.y + self.normal.z * point.z + self.distance
+    }
+
+    // Corner of the bounds furthest along the normal, if it is outside so are the bounds
+    pub fn intersects_bounds(&self, min: &Point, max: &Point) -> bool {
+        let corner = Point {
+            x: if self.normal.x >= 0.0 { max.x } else { min.x },
+            y: if self.normal.y >= 0.0 { max.y } else { min.y },
+            z: if self.normal.z >= 0.0 { max.z } else { min.z },
+        };
+
+        self.signed_distance(&corner) >= 0.0
+    }
+}
+
+pub struct RayHit<'a, T> {
+    pub point: &'a Point,
+    pub data: &'a T,
+    // Distance along the ray to the point closest to the hit
+    pub distance: f32,
+}
+
+// Lazy depth-first traversal of nodes accepted by node_filter, yielding points accepted by
+// point_filter. Siblings are found through parent links, so iterating does not allocate.
+pub struct QueryIter<'a, T, N, P>
+where
+    N: Fn(&OctreeNode) -> bool,
+    P: Fn(&Point) -> bool,
+{
+    octree: &'a Octree<T>,
+    next: Option<Index>,
+    points: std::slice::Iter<'a, Index>,
+    node_filter: N,
+    point_filter: P,
+}
+
+impl<'a, T, N, P> QueryIter<'a, T, N, P>
+where
+    N: Fn(&OctreeNode) -> bool,
+    P: Fn(&Point) -> bool,
+{
+    fn new(octree: &'a Octree<T>, node_filter: N, point_filter: P) -> Self {
+        let mut iter = Self {
+            octree,
+            next: None,
+            points: [].iter(),
+            node_filter,
+            point_filter,
+        };
+
+        iter.next = iter.next_root(None);
+
+        iter
+    }
+
+    fn accepts(&self, node_index: Index) -> bool {
+        (self.node_filter)(self.octree.nodes.get(node_index).unwrap())
+    }
+
+    // First accepted root after the given one (root, then overflow bucket)
+    fn next_root(&self, after: Option<Index>) -> Option<Index> {
+        let mut roots = self.octree.roots();
+
+        if let Some(after) = after {
+            roots.by_ref().find(|root| *root == after);
+        }
+
+        roots.find(|root| self.accepts(*root))
+    }
+
+    // Next accepted node in depth-first order
+    fn successor(&self, node_index: Index) -> Option<Index> {
+        let nodes = &self.octree.nodes;
+        let node = nodes.get(node_index).unwrap();
+
+        if let Some(child) = node.children.iter().find(|child| self.accepts(**child)) {
+            return Some(*child);
+        }
+
+        let mut current = node_index;
+
+        while let Some(parent) = nodes.get(current).unwrap().parent {
+            let siblings = &nodes.get(parent).unwrap().children;
+            let position = siblings.iter().position(|child| *child == current).unwrap();
+
+            if let Some(sibling) = siblings[position + 1..]
+                .iter()
+                .find(|sibling| self.accepts(**sibling))
+            {
+                return Some(*sibling);
+            }
+
+            current = parent;
+        }
+
+        self.next_root(Some(current))
+    }
+}
+
+impl<'a, T, N, P> Iterator for QueryIter<'a, T, N, P>
+where
+    N: Fn(&OctreeNode) -> bool,
+    P: Fn(&Point) -> bool,
+{
+    type Item = (&'a Point, &'a T);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            for point_index in self.points.by_ref() {
+                let point = self.octree.points.get(*point_index).unwrap();
+
+                if (self.point_filter)(&point.point) {
+                    return Some((&point.point, &point.data));
+                }
+            }
+
+            let node_index = self.next?;
+            self.next = self.successor(node_index);
+            self.points = self.octree.nodes.get(node_index).unwrap().points.iter();
+        }
+    }
+}
+
+// Depth-first iteration over nodes with their depth, roots (and the overflow bucket) are at 0
+pub struct NodeIter<'a, T> {
+    octree: &'a Octree<T>,
+    stack: Vec<(Index, usize)>,
+}
+
+impl<'a, T> Iterator for NodeIter<'a, T> {
+    type Item = (Index, &'a OctreeNode, usize);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (index, depth) = self.stack.pop()?;
+        let node = self.octree.nodes.get(index).unwrap();
+
+        // Reversed so the first child is visited first
+        self.stack
+            .extend(node.children.iter().rev().map(|child| (*child, depth + 1)));
+
+        Some((index, node, depth))
+    }
+}
+
+// Shape and memory usage of an octree, see Octree::stats
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct OctreeStats {
+    // Depth of the deepest leaf
+    pub depth: usize,
+    pub nodes: usize,
+    pub leaves: usize,
+    pub points: usize,
+    // points_per_leaf[n] is the number of leaves holding n points
+    pub points_per_leaf: Vec<usize>,
+    pub bytes_held: usize,
+}
+
+impl OctreeStats {
+    pub fn empty_leaves(&self) -> usize {
+        self.points_per_leaf.first().copied().unwrap_or(0)
+    }
+
+    pub fn empty_leaf_ratio(&self) -> f32 {
+        if self.leaves == 0 {
+            return 0.0;
+        }
+
+        self.empty_leaves() as f32 / self.leaves as f32
+    }
+}
+
+// Node or point index ordered by distance, used for best-first traversal
+struct QueueItem {
+    distance: f32,
+    index: Index,
+}
+
+impl PartialEq for QueueItem {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for QueueItem {}
+
+impl PartialOrd for QueueItem {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for QueueItem {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.distance.total_cmp(&other.distance)
+    }
 }
 
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct OctreeNode {
     pub points: Vec<Index>,
     pub children: Vec<Index>,
+    pub parent: Option<Index>,
     // center, min, max
     pub bounds: (Point, Point, Point),
+    // Aggregate of all points below the node, only set by update_summaries
+    pub summary: Option<NodeSummary>,
+}
+
+// Count, center of mass and summed velocity of the points below a node (all weigh the same)
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct NodeSummary {
+    pub count: usize,
+    pub center_of_mass: Point,
+    pub velocity_sum: Point,
+}
+
+// Item visited by Octree::for_each_approximate
+pub enum Approximation<'a, T> {
+    // Point close enough to be visited on its own
+    Exact(&'a T),
+    // Far away points summarized by their node
+    Group(&'a NodeSummary),
 }
 
 // Octree impl
 impl<T> Octree<T> {
     pub fn new(max_points: usize) -> Self {
+        Self::with_config(OctreeConfig {
+            max_points,
+            ..Default::default()
+        })
+    }
+
+    pub fn with_config(config: OctreeConfig) -> Self {
         Self {
-            max_points: max_points,
+            config,
             root: None,
+            overflow: None,
             nodes: Arena::new(),
             points: Arena::new(),
+            spare_points: vec![],
+            spare_children: vec![],
+            allocations: 0,
         }
     }
 
-    pub fn create_root(&mut self, center: Point, min: Point, max: Point) -> Index {
-        let index = self.nodes.insert(OctreeNode {
-            points: vec![],
-            children: vec![],
-            bounds: (center, min, max),
-        });
+    pub fn create_root(
+        &mut self,
+        center: impl ToPoint,
+        min: impl ToPoint,
+        max: impl ToPoint,
+    ) -> Index {
+        let index = self.insert_node(
+            None,
+            (center.to_point(), min.to_point(), max.to_point()),
+            true,
+        );
 
         self.root = Some(index);
 
         index
     }
 
-    pub fn insert(&mut self, point: Point, data: T) -> Index {
-        let index = self.points.insert(PointWrapper { data, point });
+    // Removes all points and every node except the (now empty) root and overflow bucket.
+    // Allocated storage is kept, so refilling a similar tree does not allocate.
+    // Indices returned before clearing must not be used afterwards.
+    pub fn clear(&mut self) {
+        let (root, overflow) = (self.root, self.overflow);
 
-        let point = self.points.get(index).unwrap();
+        self.nodes.retain(|index, node| {
+            recycle(
+                &mut self.spare_children,
+                &mut self.allocations,
+                std::mem::take(&mut node.children),
+            );
 
-        if let Some(root) = self.root {
-            let mut node_index = root;
-            let mut node = self.nodes.get(node_index).unwrap();
+            if Some(index) == root || Some(index) == overflow {
+                node.points.clear();
+                return true;
+            }
 
-            loop {
-                // Check if point is in bounds
-                // if !Self::is_point_in_bounds(node, &point.point) {
-                //     continue;
-                // }
+            recycle(
+                &mut self.spare_points,
+                &mut self.allocations,
+                std::mem::take(&mut node.points),
+            );
 
-                if node.children.len() == 0 {
-                    let mut_node = self.nodes.get_mut(node_index).unwrap();
-                    mut_node.points.push(index);
+            false
+        });
 
-                    if mut_node.points.len() > self.max_points {
-                        self.subdivide(node_index);
-                    }
+        // A root that had children has no points vector yet
+        if let Some(root) = root {
+            let node = self.nodes.get_mut(root).unwrap();
 
-                    break;
-                } else {
-                    let mut selected_index = -1;
-                    let mut min_distance = f32::MAX;
+            if node.points.capacity() == 0 {
+                node.points = take_vec(
+                    &mut self.spare_points,
+                    &mut self.allocations,
+                    self.config.max_points + 1,
+                );
+            }
+        }
 
-                    for (i, child_index) in node.children.iter().enumerate() {
-                        let child = self.nodes.get(*child_index).unwrap();
+        self.points.clear();
+    }
 
-                        // Find closest center to child
-                        let distance = (child.bounds.0.x - point.point.x).powi(2)
-                            + (child.bounds.0.y - point.point.y).powi(2)
-                            + (child.bounds.0.z - point.point.z).powi(2);
+    // All nodes in depth-first order with their depth
+    pub fn nodes_with_depth(&self) -> NodeIter<'_, T> {
+        let mut stack = self.roots().map(|root| (root, 0)).collect::<Vec<_>>();
+        stack.reverse();
 
-                        if distance < min_distance {
-                            min_distance = distance;
-                            selected_index = i as i32;
-                        }
+        NodeIter {
+            octree: self,
+            stack,
+        }
+    }
 
-                        // if Self::is_point_in_bounds(child, &point.point) {
-                        //     selected_index = i as i32;
-                        //     break;
-                        // }
-                    }
+    pub fn leaves_with_depth(&self) -> impl Iterator<Item = (Index, &OctreeNode, usize)> + '_ {
+        self.nodes_with_depth()
+            .filter(|(_, node, _)| node.children.is_empty())
+    }
 
-                    if selected_index == -1 {
-                        break;
-                    }
+    // Walks the whole tree, meant for tuning and debugging rather than every query
+    pub fn stats(&self) -> OctreeStats {
+        let mut stats = OctreeStats {
+            points: self.points.len(),
+            bytes_held: self.bytes_held(),
+            ..Default::default()
+        };
+
+        for (_, node, depth) in self.nodes_with_depth() {
+            stats.nodes += 1;
+
+            if !node.children.is_empty() {
+                continue;
+            }
+
+            let count = node.points.len();
+
+            if stats.points_per_leaf.len() <= count {
+                stats.points_per_leaf.resize(count + 1, 0);
+            }
+
+            stats.points_per_leaf[count] += 1;
+            stats.leaves += 1;
+            stats.depth = stats.depth.max(depth);
+        }
+
+        stats
+    }
+
+    // Number of times the arenas or node vectors had to grow since the octree was created
+    pub fn allocations(&self) -> usize {
+        self.allocations
+    }
+
+    // Approximate heap memory held by the arenas and node vectors, including unused capacity
+    pub fn bytes_held(&self) -> usize {
+        let vectors = self
+            .nodes
+            .iter()
+            .map(|(_, node)| node.points.capacity() + node.children.capacity())
+            .chain(
+                self.spare_points
+                    .iter()
+                    .chain(self.spare_children.iter())
+                    .map(Vec::capacity),
+            )
+            .sum::<usize>();
 
-                    node_index = node.children[selected_index as usize];
-                    node = self.nodes.get(node_index).unwrap();
+        self.nodes.capacity() * std::mem::size_of::<OctreeNode>()
+            + self.points.capacity() * std::mem::size_of::<PointWrapper<T>>()
+            + vectors * std::mem::size_of::<Index>()
+            + (self.spare_points.capacity() + self.spare_children.capacity())
+                * std::mem::size_of::<Vec<Index>>()
+    }
+
+    pub fn insert(&mut self, point: impl ToPoint, data: T) -> Result<Index, SpatialIndexError> {
+        let index = self.insert_point(PointWrapper {
+            data,
+            point: point.to_point(),
+            leaf: None,
+        });
+
+        if let Err(error) = self.place(index) {
+            self.points.remove(index);
+            return Err(error);
+        }
+
+        Ok(index)
+    }
+
+    pub fn get(&self, index: Index) -> Option<&T> {
+        self.points.get(index).map(|point| &point.data)
+    }
+
+    pub fn get_mut(&mut self, index: Index) -> Option<&mut T> {
+        self.points.get_mut(index).map(|point| &mut point.data)
+    }
+
+    // Moves a point, returns true if it left its cell and was relocated to another leaf.
+    // A move refused by the out of bounds policy leaves the point where it was.
+    pub fn update(
+        &mut self,
+        index: Index,
+        new_point: impl ToPoint,
+    ) -> Result<bool, SpatialIndexError> {
+        let new_point = new_point.to_point();
+
+        if self.refuses(&new_point) {
+            return Err(SpatialIndexError::OutOfBounds(new_point));
+        }
+
+        let point = match self.points.get_mut(index) {
+            Some(point) => point,
+            None => return Ok(false),
+        };
+
+        point.point = new_point;
+
+        let stays = match point.leaf {
+            // Overflowing points move back once the root contains them again
+            Some(leaf) if Some(leaf) == self.overflow => !self.root.map_or(false, |root| {
+                Self::is_point_in_bounds(self.nodes.get(root).unwrap(), &point.point)
+            }),
+            Some(leaf) => Self::is_point_in_bounds(self.nodes.get(leaf).unwrap(), &point.point),
+            None => false,
+        };
+
+        if stays {
+            return Ok(false);
+        }
+
+        self.relocate(index)?;
+
+        Ok(true)
+    }
+
+    // Removes a point from its leaf and inserts it again starting at the root
+    pub fn relocate(&mut self, index: Index) -> Result<(), SpatialIndexError> {
+        let leaf = match self.points.get_mut(index) {
+            Some(point) => point.leaf.take(),
+            None => return Ok(()),
+        };
+
+        if let Some(leaf) = leaf {
+            self.detach(leaf, index);
+        }
+
+        self.place(index)
+    }
+
+    pub fn remove(&mut self, index: Index) -> Option<T> {
+        let point = self.points.remove(index)?;
+
+        if let Some(leaf) = point.leaf {
+            self.detach(leaf, index);
+        }
+
+        Some(point.data)
+    }
+
+    // Removes a point index from a leaf and collapses the nodes above it if possible
+    fn detach(&mut self, leaf: Index, index: Index) {
+        let node = self.nodes.get_mut(leaf).unwrap();
+        node.points.retain(|point_index| *point_index != index);
+
+        if let Some(parent) = node.parent {
+            self.collapse(parent);
+        }
+    }
+
+    // Merges the children of a node back into it while their combined point count is below max_points
+    fn collapse(&mut self, node_index: Index) {
+        let mut next = Some(node_index);
+
+        while let Some(node_index) = next {
+            let node = self.nodes.get(node_index).unwrap();
+            let mut count = 0;
+
+            if node.children.is_empty() {
+                return;
+            }
+
+            for child_index in node.children.iter() {
+                let child = self.nodes.get(*child_index).unwrap();
+
+                if !child.children.is_empty() {
+                    return;
+                }
+
+                count += child.points.len();
+            }
+
+            if count >= self.config.max_points {
+                return;
+            }
+
+            let node = self.nodes.get_mut(node_index).unwrap();
+            let children = std::mem::take(&mut node.children);
+            let mut points: Option<Vec<Index>> = None;
+
+            for child_index in children.iter() {
+                let mut child = self.nodes.remove(*child_index).unwrap();
+
+                match points.as_mut() {
+                    Some(points) => {
+                        for point_index in child.points.iter() {
+                            push_counted(points, *point_index, &mut self.allocations);
+                        }
+                    }
+                    // Internal nodes have no points vector, the one of the first child is used
+                    None => points = Some(std::mem::take(&mut child.points)),
                 }
+
+                self.recycle_node(child);
+            }
+
+            let points = points.unwrap();
+
+            recycle(&mut self.spare_children, &mut self.allocations, children);
+
+            for point_index in points.iter() {
+                self.points.get_mut(*point_index).unwrap().leaf = Some(node_index);
+            }
+
+            let node = self.nodes.get_mut(node_index).unwrap();
+            node.points = points;
+            next = node.parent;
+        }
+    }
+
+    // Whether the out of bounds policy refuses the point
+    fn refuses(&self, point: &Point) -> bool {
+        let in_root = match self.root {
+            Some(root) => Self::is_point_in_bounds(self.nodes.get(root).unwrap(), point),
+            // The first point gets a root around it
+            None => point.is_finite(),
+        };
+
+        !in_root
+            && match self.config.out_of_bounds {
+                OutOfBoundsPolicy::Grow => !point.is_finite(),
+                OutOfBoundsPolicy::Overflow => false,
+                OutOfBoundsPolicy::Reject => true,
             }
+    }
+
+    // Puts a stored point into the leaf containing it, applying the out of bounds policy
+    fn place(&mut self, index: Index) -> Result<(), SpatialIndexError> {
+        let point = self.points.get(index).unwrap().point.clone();
+
+        if self.refuses(&point) {
+            return Err(SpatialIndexError::OutOfBounds(point));
         }
 
-        index
+        let root = match self.root {
+            Some(root) => root,
+            None => {
+                let (center, min, max) = bounding_cube_of(&[(point.clone(), ())]);
+                self.create_root(center, min, max)
+            }
+        };
+
+        if !Self::is_point_in_bounds(self.nodes.get(root).unwrap(), &point) {
+            if self.config.out_of_bounds == OutOfBoundsPolicy::Overflow {
+                let overflow = self.overflow_node();
+                push_counted(
+                    &mut self.nodes.get_mut(overflow).unwrap().points,
+                    index,
+                    &mut self.allocations,
+                );
+                self.points.get_mut(index).unwrap().leaf = Some(overflow);
+
+                return Ok(());
+            }
+
+            // The only other policy that keeps the point is growing the root
+            self.grow(&point);
+        }
+
+        self.insert_into(self.root.unwrap(), index);
+
+        Ok(())
     }
 
-    fn is_point_in_bounds(node: &OctreeNode, point: &Point) -> bool {
-        let (_, min, max) = &node.bounds;
+    // Doubles the root towards the point until it contains it, the old root ends up as a
+    // descendant of the new one
+    fn grow(&mut self, point: &Point) {
+        let old_root = self.root.unwrap();
+        let (old_center, min, max) = self.nodes.get(old_root).unwrap().bounds.clone();
 
-        point.x >= min.x
+        // Doubling a root without width along an axis never reaches the point, the tree is
+        // rebuilt in a cube around the root and the point instead
+        if is_too_thin(&min, &max) {
+            let low = Point {
+                x: min.x.min(point.x),
+                y: min.y.min(point.y),
+                z: min.z.min(point.z),
+            };
+            let high = Point {
+                x: max.x.max(point.x),
+                y: max.y.max(point.y),
+                z: max.z.max(point.z),
+            };
+
+            self.reroot(bounding_cube(&low, &high));
+            return;
+        }
+
+        let grow_axis = |value: f32, min: f32, max: f32| {
+            if value < min {
+                (min - (max - min), max)
+            } else {
+                (min, max + (max - min))
+            }
+        };
+
+        // Bounds of every new level, computed before the tree is touched
+        let mut levels = vec![];
+        let (mut min, mut max) = (min, max);
+
+        while !(min.x <= point.x
             && point.x <= max.x
-            && point.y >= min.y
+            && min.y <= point.y
             && point.y <= max.y
-            && point.z >= min.z
-            && point.z <= max.z
+            && min.z <= point.z
+            && point.z <= max.z)
+        {
+            let (min_x, max_x) = grow_axis(point.x, min.x, max.x);
+            let (min_y, max_y) = grow_axis(point.y, min.y, max.y);
+            let (min_z, max_z) = grow_axis(point.z, min.z, max.z);
+
+            min = Point {
+                x: min_x,
+                y: min_y,
+                z: min_z,
+            };
+            max = Point {
+                x: max_x,
+                y: max_y,
+                z: max_z,
+            };
+            levels.push((min.clone(), max.clone()));
+        }
+
+        let (min, max) = levels.pop().unwrap();
+        let root = self.create_root(
+            Point {
+                x: (min.x + max.x) / 2.0,
+                y: (min.y + max.y) / 2.0,
+                z: (min.z + max.z) / 2.0,
+            },
+            min,
+            max,
+        );
+
+        // Split the new levels down to the old root and put it in place of the child there
+        let mut centers = vec![];
+        let mut node_index = root;
+
+        loop {
+            self.subdivide(node_index);
+
+            let node = self.nodes.get(node_index).unwrap();
+            let octant = Self::octant(node, &old_center);
+            centers.push(node.bounds.0.clone());
+
+            if levels.pop().is_some() {
+                node_index = node.children[octant];
+                continue;
+            }
+
+            let node = self.nodes.get_mut(node_index).unwrap();
+            let replaced = std::mem::replace(&mut node.children[octant], old_root);
+
+            let replaced = self.nodes.remove(replaced).unwrap();
+            self.recycle_node(replaced);
+            self.nodes.get_mut(old_root).unwrap().parent = Some(node_index);
+            break;
+        }
+
+        // Points on a face of the old root that touches one of the new centers belong to
+        // another child by octant, move them there so descending by octant finds them
+        let moved = self
+            .points
+            .iter()
+            .filter(|(_, point)| {
+                point.leaf.is_some()
+                    && point.leaf != self.overflow
+                    && centers.iter().any(|center| {
+                        point.point.x == center.x
+                            || point.point.y == center.y
+                            || point.point.z == center.z
+                    })
+            })
+            .map(|(index, _)| index)
+            .collect::<Vec<_>>();
+
+        for index in moved {
+            let leaf = self.points.get_mut(index).unwrap().leaf.take().unwrap();
+            self.detach(leaf, index);
+            self.insert_into(root, index);
+        }
+    }
+
+    // Replaces the tree by a root with the given bounds and inserts its points again
+    fn reroot(&mut self, bounds: (Point, Point, Point)) {
+        let overflow = self.overflow;
+        let points = self
+            .points
+            .iter()
+            .filter(|(_, point)| point.leaf.is_some() && point.leaf != overflow)
+            .map(|(index, _)| index)
+            .collect::<Vec<_>>();
+        let nodes = self
+            .nodes
+            .iter()
+            .map(|(index, _)| index)
+            .filter(|index| Some(*index) != overflow)
+            .collect::<Vec<_>>();
+
+        for index in nodes {
+            let node = self.nodes.remove(index).unwrap();
+            self.recycle_node(node);
+        }
+
+        let (center, min, max) = bounds;
+        let root = self.create_root(center, min, max);
+
+        for index in points {
+            self.insert_into(root, index);
+        }
+    }
+
+    fn overflow_node(&mut self) -> Index {
+        if let Some(overflow) = self.overflow {
+            return overflow;
+        }
+
+        let overflow = self.insert_node(
+            None,
+            (
+                Point {
+                    x: 0.0,
+                    y: 0.0,
+                    z: 0.0,
+                },
+                Point {
+                    x: f32::MIN,
+                    y: f32::MIN,
+                    z: f32::MIN,
+                },
+                Point {
+                    x: f32::MAX,
+                    y: f32::MAX,
+                    z: f32::MAX,
+                },
+            ),
+            true,
+        );
+
+        self.overflow = Some(overflow);
+
+        overflow
+    }
+
+    // Root node followed by the overflow bucket
+    fn roots(&self) -> impl Iterator<Item = Index> {
+        self.root.into_iter().chain(self.overflow)
+    }
+
+    // Index of the child containing the point (order as created in subdivide)
+    fn octant(node: &OctreeNode, point: &Point) -> usize {
+        octant_around(&node.bounds.0, point)
+    }
+
+    fn insert_into(&mut self, node_index: Index, index: Index) {
+        let point = self.points.get(index).unwrap();
+        let mut node_index = node_index;
+        let mut node = self.nodes.get(node_index).unwrap();
+
+        loop {
+            if node.children.is_empty() {
+                let mut_node = self.nodes.get_mut(node_index).unwrap();
+                push_counted(&mut mut_node.points, index, &mut self.allocations);
+                self.points.get_mut(index).unwrap().leaf = Some(node_index);
+
+                if mut_node.points.len() > self.config.max_points && self.can_subdivide(node_index)
+                {
+                    self.subdivide(node_index);
+                }
+
+                break;
+            } else {
+                node_index = node.children[Self::octant(node, &point.point)];
+                node = self.nodes.get(node_index).unwrap();
+            }
+        }
+    }
+
+    fn is_point_in_bounds(node: &OctreeNode, point: &Point) -> bool {
+        let (_, min, max) = &node.bounds;
+
+        point.x >= min.x
+            && point.x <= max.x
+            && point.y >= min.y
+            && point.y <= max.y
+            && point.z >= min.z
+            && point.z <= max.z
+    }
+
+    fn subdivide(&mut self, node_index: Index) {
+        let bounds = child_bounds(&self.nodes.get(node_index).unwrap().bounds);
+        let mut children = take_vec(&mut self.spare_children, &mut self.allocations, 8);
+
+        for (center, min, max) in bounds {
+            children.push(self.create_node(node_index, center, min, max));
+        }
+
+        let mut_node = self.nodes.get_mut(node_index).unwrap();
+        let empty = std::mem::replace(&mut mut_node.children, children);
+        let points = std::mem::take(&mut mut_node.points);
+
+        recycle(&mut self.spare_children, &mut self.allocations, empty);
+
+        // Fix points
+        // for child_index in children.iter() {
+        // let child = self.nodes.get_mut(*child_index).unwrap();
+
+        // Assign points to the children containing them
+        for point_index in points.iter() {
+            let node = self.nodes.get(node_index).unwrap();
+            let point = &self.points.get(*point_index).unwrap().point;
+            let child_index = node.children[Self::octant(node, point)];
+
+            let child = self.nodes.get_mut(child_index).unwrap();
+            push_counted(&mut child.points, *point_index, &mut self.allocations);
+            self.points.get_mut(*point_index).unwrap().leaf = Some(child_index);
+        }
+
+        // Only leaves hold points
+        recycle(&mut self.spare_points, &mut self.allocations, points);
+
+        // All points can end up in the same child
+        for octant in 0..8 {
+            let child_index = self.nodes.get(node_index).unwrap().children[octant];
+
+            if self.nodes.get(child_index).unwrap().points.len() > self.config.max_points
+                && self.can_subdivide(child_index)
+            {
+                self.subdivide(child_index);
+            }
+        }
+    }
+
+    // Depth and cell size limits stop subdivision of (near) coincident points
+    fn can_subdivide(&self, node_index: Index) -> bool {
+        let (_, min, max) = &self.nodes.get(node_index).unwrap().bounds;
+        let child_size = (max.x - min.x).min(max.y - min.y).min(max.z - min.z) / 2.0;
+
+        self.depth(node_index) < self.config.max_depth && child_size >= self.config.min_cell_size
+    }
+
+    pub fn depth(&self, node_index: Index) -> usize {
+        let mut depth = 0;
+        let mut node = self.nodes.get(node_index).unwrap();
+
+        while let Some(parent) = node.parent {
+            depth += 1;
+            node = self.nodes.get(parent).unwrap();
+        }
+
+        depth
+    }
+
+    fn create_node(&mut self, parent: Index, center: Point, min: Point, max: Point) -> Index {
+        self.insert_node(Some(parent), (center, min, max), true)
+    }
+
+    // Inserts an empty node, a leaf gets a spare points vector with room for max_points + 1
+    fn insert_node(
+        &mut self,
+        parent: Option<Index>,
+        bounds: (Point, Point, Point),
+        leaf: bool,
+    ) -> Index {
+        let points = if leaf {
+            take_vec(
+                &mut self.spare_points,
+                &mut self.allocations,
+                self.config.max_points + 1,
+            )
+        } else {
+            vec![]
+        };
+
+        let node = OctreeNode {
+            bounds,
+            children: vec![],
+            parent,
+            summary: None,
+            points,
+        };
+
+        if self.nodes.len() == self.nodes.capacity() {
+            self.allocations += 1;
+        }
+
+        self.nodes.insert(node)
+    }
+
+    fn insert_point(&mut self, point: PointWrapper<T>) -> Index {
+        if self.points.len() == self.points.capacity() {
+            self.allocations += 1;
+        }
+
+        self.points.insert(point)
+    }
+
+    // Keeps the vectors of a removed node for reuse
+    fn recycle_node(&mut self, node: OctreeNode) {
+        recycle(&mut self.spare_points, &mut self.allocations, node.points);
+        recycle(
+            &mut self.spare_children,
+            &mut self.allocations,
+            node.children,
+        );
+    }
+
+    pub fn find_neighbors(&self, point: impl ToPoint) -> Vec<&T> {
+        let point = &point.to_point();
+        let mut neighbors = vec![];
+
+        if let Some(root) = self.root {
+            let mut node = self.nodes.get(root).unwrap();
+
+            if !Self::is_point_in_bounds(node, point) {
+                if let Some(overflow) = self.overflow {
+                    node = self.nodes.get(overflow).unwrap();
+                }
+            }
+
+            // Find the leaf that contains the point
+            while !node.children.is_empty() {
+                node = self
+                    .nodes
+                    .get(node.children[Self::octant(node, point)])
+                    .unwrap();
+            }
+
+            for point_index in node.points.iter() {
+                let neighbor = self.points.get(*point_index).unwrap();
+
+                neighbors.push(&neighbor.data);
+            }
+        }
+
+        neighbors
+    }
+
+    // Returns all points within radius, visiting every node that overlaps the query sphere
+    pub fn find_within_radius(&self, point: impl ToPoint, radius: f32) -> Vec<&T> {
+        self.neighbors_within_radius(point, radius).collect()
+    }
+
+    // Lazily yields all points within radius without allocating
+    pub fn neighbors_within_radius(
+        &self,
+        point: impl ToPoint,
+        radius: f32,
+    ) -> impl Iterator<Item = &T> + '_ {
+        self.points_within_radius(point, radius)
+            .map(|(_, data)| data)
+    }
+
+    // Same as neighbors_within_radius, also yielding the position of every point
+    pub fn points_within_radius(
+        &self,
+        point: impl ToPoint,
+        radius: f32,
+    ) -> impl Iterator<Item = (&Point, &T)> + '_ {
+        let radius_squared = radius * radius;
+        let (node_point, point) = (point.to_point(), point.to_point());
+
+        self.query(
+            move |node| Self::distance_to_bounds_squared(node, &node_point) <= radius_squared,
+            move |other| other.distance_squared(&point) <= radius_squared,
+        )
+    }
+
+    // Calls visit for every point within radius without allocating
+    pub fn for_each_neighbor<F>(&self, point: impl ToPoint, radius: f32, visit: F)
+    where
+        F: FnMut(&T),
+    {
+        self.neighbors_within_radius(point, radius).for_each(visit);
+    }
+
+    // Squared distance from point to the closest point of the node bounds (0 if inside)
+    fn distance_to_bounds_squared(node: &OctreeNode, point: &Point) -> f32 {
+        let (_, min, max) = &node.bounds;
+
+        let dx = (min.x - point.x).max(0.0).max(point.x - max.x);
+        let dy = (min.y - point.y).max(0.0).max(point.y - max.y);
+        let dz = (min.z - point.z).max(0.0).max(point.z - max.z);
+
+        dx * dx + dy * dy + dz * dz
+    }
+
+    // Returns the k closest points sorted by distance (best-first traversal)
+    pub fn k_nearest(&self, point: impl ToPoint, k: usize) -> Vec<&T> {
+        let point = &point.to_point();
+        let mut nearest: BinaryHeap<QueueItem> = BinaryHeap::with_capacity(k + 1);
+
+        if k > 0 {
+            let mut queue = self
+                .roots()
+                .map(|index| {
+                    Reverse(QueueItem {
+                        distance: Self::distance_to_bounds_squared(
+                            self.nodes.get(index).unwrap(),
+                            point,
+                        ),
+                        index,
+                    })
+                })
+                .collect::<BinaryHeap<_>>();
+
+            while let Some(Reverse(item)) = queue.pop() {
+                // No remaining node can contain a closer point
+                if nearest.len() == k && item.distance > nearest.peek().unwrap().distance {
+                    break;
+                }
+
+                let node = self.nodes.get(item.index).unwrap();
+
+                for point_index in node.points.iter() {
+                    let distance = self
+                        .points
+                        .get(*point_index)
+                        .unwrap()
+                        .point
+                        .distance_squared(point);
+
+                    if nearest.len() < k {
+                        nearest.push(QueueItem {
+                            distance,
+                            index: *point_index,
+                        });
+                    } else if distance < nearest.peek().unwrap().distance {
+                        nearest.pop();
+                        nearest.push(QueueItem {
+                            distance,
+                            index: *point_index,
+                        });
+                    }
+                }
+
+                for child_index in node.children.iter() {
+                    let child = self.nodes.get(*child_index).unwrap();
+
+                    queue.push(Reverse(QueueItem {
+                        distance: Self::distance_to_bounds_squared(child, point),
+                        index: *child_index,
+                    }));
+                }
+            }
+        }
+
+        nearest
+            .into_sorted_vec()
+            .iter()
+            .map(|item| &self.points.get(item.index).unwrap().data)
+            .collect()
+    }
+
+    // Recomputes the summary of every node bottom-up. Insert, update and remove do not keep
+    // summaries up to date, so this has to be called again after changing the octree.
+    pub fn update_summaries<V>(&mut self, velocity: V)
+    where
+        V: Fn(&T) -> Point,
+    {
+        for root in [self.root, self.overflow].into_iter().flatten() {
+            self.summarize(root, &velocity);
+        }
+    }
+
+    fn summarize<V>(&mut self, node_index: Index, velocity: &V) -> NodeSummary
+    where
+        V: Fn(&T) -> Point,
+    {
+        let mut count = 0;
+        let mut position_sum = Point::ZERO;
+        let mut velocity_sum = Point::ZERO;
+
+        for point_index in self.nodes.get(node_index).unwrap().points.iter() {
+            let point = self.points.get(*point_index).unwrap();
+
+            count += 1;
+            position_sum.add_scaled(&point.point, 1.0);
+            velocity_sum.add_scaled(&velocity(&point.data), 1.0);
+        }
+
+        for i in 0..self.nodes.get(node_index).unwrap().children.len() {
+            let child = self.nodes.get(node_index).unwrap().children[i];
+            let child = self.summarize(child, velocity);
+
+            count += child.count;
+            position_sum.add_scaled(&child.center_of_mass, child.count as f32);
+            velocity_sum.add_scaled(&child.velocity_sum, 1.0);
+        }
+
+        let node = self.nodes.get_mut(node_index).unwrap();
+
+        // Empty nodes use their center so the summary is never NaN
+        let center_of_mass = if count > 0 {
+            Point {
+                x: position_sum.x / count as f32,
+                y: position_sum.y / count as f32,
+                z: position_sum.z / count as f32,
+            }
+        } else {
+            node.bounds.0.clone()
+        };
+
+        let summary = NodeSummary {
+            count,
+            center_of_mass,
+            velocity_sum,
+        };
+
+        node.summary = Some(summary.clone());
+
+        summary
+    }
+
+    // Barnes-Hut traversal: points near the query point are visited one by one, nodes that
+    // look small from it (size / distance to center of mass < theta) are visited as a
+    // single summary. Only nodes overlapping the radius are visited, groups are visited if
+    // their center of mass is within radius. theta = 0 visits every point exactly. Nodes
+    // containing the query point are never summarized, whatever theta is.
+    pub fn for_each_approximate<F>(
+        &self,
+        point: impl ToPoint,
+        radius: f32,
+        theta: f32,
+        mut visit: F,
+    ) where
+        F: FnMut(Approximation<'_, T>),
+    {
+        let point = &point.to_point();
+
+        for root in self.roots() {
+            self.approximate_node(root, point, radius * radius, theta, &mut visit);
+        }
+    }
+
+    fn approximate_node<F>(
+        &self,
+        node_index: Index,
+        point: &Point,
+        radius_squared: f32,
+        theta: f32,
+        visit: &mut F,
+    ) where
+        F: FnMut(Approximation<'_, T>),
+    {
+        let node = self.nodes.get(node_index).unwrap();
+
+        if Self::distance_to_bounds_squared(node, point) > radius_squared {
+            return;
+        }
+
+        // The overflow bucket has infinite size and is never summarized. A large theta would
+        // summarize the node around the query point, and with it the point itself.
+        if let Some(summary) = node
+            .summary
+            .as_ref()
+            .filter(|summary| summary.count > 0 && !Self::is_point_in_bounds(node, point))
+        {
+            let (_, min, max) = &node.bounds;
+            let size = (max.x - min.x).max(max.y - min.y).max(max.z - min.z);
+            let distance_squared = summary.center_of_mass.distance_squared(point);
+
+            if size * size < theta * theta * distance_squared {
+                if distance_squared <= radius_squared {
+                    visit(Approximation::Group(summary));
+                }
+
+                return;
+            }
+        }
+
+        for point_index in node.points.iter() {
+            let other = self.points.get(*point_index).unwrap();
+
+            if other.point.distance_squared(point) <= radius_squared {
+                visit(Approximation::Exact(&other.data));
+            }
+        }
+
+        for child_index in node.children.iter() {
+            self.approximate_node(*child_index, point, radius_squared, theta, visit);
+        }
+    }
+
+    fn query<N, P>(&self, node_filter: N, point_filter: P) -> QueryIter<'_, T, N, P>
+    where
+        N: Fn(&OctreeNode) -> bool,
+        P: Fn(&Point) -> bool,
+    {
+        QueryIter::new(self, node_filter, point_filter)
+    }
+
+    // Lazily yields all points inside the axis-aligned box (inclusive)
+    pub fn query_aabb(
+        &self,
+        min: impl ToPoint,
+        max: impl ToPoint,
+    ) -> impl Iterator<Item = (&Point, &T)> + '_ {
+        let (node_min, node_max) = (min.to_point(), max.to_point());
+        let (min, max) = (node_min.clone(), node_max.clone());
+
+        self.query(
+            move |node| {
+                let (_, bounds_min, bounds_max) = &node.bounds;
+
+                bounds_min.x <= node_max.x
+                    && bounds_max.x >= node_min.x
+                    && bounds_min.y <= node_max.y
+                    && bounds_max.y >= node_min.y
+                    && bounds_min.z <= node_max.z
+                    && bounds_max.z >= node_min.z
+            },
+            move |point| {
+                point.x >= min.x
+                    && point.x <= max.x
+                    && point.y >= min.y
+                    && point.y <= max.y
+                    && point.z >= min.z
+                    && point.z <= max.z
+            },
+        )
+    }
+
+    // Lazily yields all points inside the convex volume bounded by the planes (e.g. a camera frustum)
+    pub fn query_frustum(&self, planes: &[Plane]) -> impl Iterator<Item = (&Point, &T)> + '_ {
+        let node_planes = planes.to_vec();
+        let planes = planes.to_vec();
+
+        self.query(
+            move |node| {
+                let (_, min, max) = &node.bounds;
+
+                node_planes
+                    .iter()
+                    .all(|plane| plane.intersects_bounds(min, max))
+            },
+            move |point| {
+                planes
+                    .iter()
+                    .all(|plane| plane.signed_distance(point) >= 0.0)
+            },
+        )
+    }
+
+    // Returns the closest point within radius of the ray (front-to-back traversal)
+    pub fn raycast(
+        &self,
+        origin: impl ToPoint,
+        direction: impl ToPoint,
+        max_distance: f32,
+        radius: f32,
+    ) -> Option<RayHit<'_, T>> {
+        let (origin, direction) = (origin.to_point(), direction.to_point());
+        let mut hit = None;
+
+        self.traverse_ray(&origin, &direction, max_distance, radius, |candidate| {
+            hit = Some(candidate);
+            false
+        });
+
+        hit
+    }
+
+    // Returns all points within radius of the ray sorted by distance along the ray
+    pub fn raycast_all(
+        &self,
+        origin: impl ToPoint,
+        direction: impl ToPoint,
+        max_distance: f32,
+        radius: f32,
+    ) -> Vec<RayHit<'_, T>> {
+        let (origin, direction) = (origin.to_point(), direction.to_point());
+        let mut hits = vec![];
+
+        self.traverse_ray(&origin, &direction, max_distance, radius, |hit| {
+            hits.push(hit);
+            true
+        });
+
+        hits
+    }
+
+    // Visits hits in order of distance along the ray until on_hit returns false
+    fn traverse_ray<'a, F>(
+        &'a self,
+        origin: &Point,
+        direction: &Point,
+        max_distance: f32,
+        radius: f32,
+        mut on_hit: F,
+    ) where
+        F: FnMut(RayHit<'a, T>) -> bool,
+    {
+        let length = direction.distance_squared(&Point::ZERO).sqrt();
+
+        if length.is_nan() || length <= 0.0 {
+            return;
+        }
+
+        let direction = Point {
+            x: direction.x / length,
+            y: direction.y / length,
+            z: direction.z / length,
+        };
+
+        // Nodes and point candidates share one queue ordered by distance along the ray
+        let mut nodes = BinaryHeap::new();
+        let mut candidates: BinaryHeap<Reverse<QueueItem>> = BinaryHeap::new();
+
+        for root in self.roots() {
+            if let Some(distance) = Self::ray_enters_node(
+                self.nodes.get(root).unwrap(),
+                origin,
+                &direction,
+                max_distance,
+                radius,
+            ) {
+                nodes.push(Reverse(QueueItem {
+                    distance,
+                    index: root,
+                }));
+            }
+        }
+
+        loop {
+            let next_node = nodes.peek().map(|Reverse(item)| item.distance);
+            let next_candidate = candidates.peek().map(|Reverse(item)| item.distance);
+
+            // Emit candidates that no unvisited node can beat
+            if let Some(distance) = next_candidate {
+                if next_node.map_or(true, |node_distance| distance <= node_distance) {
+                    let Reverse(item) = candidates.pop().unwrap();
+                    let point = self.points.get(item.index).unwrap();
+
+                    let hit = RayHit {
+                        point: &point.point,
+                        data: &point.data,
+                        distance: item.distance,
+                    };
+
+                    if !on_hit(hit) {
+                        return;
+                    }
+
+                    continue;
+                }
+            }
+
+            let Reverse(item) = match nodes.pop() {
+                Some(item) => item,
+                None => return,
+            };
+
+            let node = self.nodes.get(item.index).unwrap();
+
+            for point_index in node.points.iter() {
+                let point = &self.points.get(*point_index).unwrap().point;
+                let offset = Point {
+                    x: point.x - origin.x,
+                    y: point.y - origin.y,
+                    z: point.z - origin.z,
+                };
+
+                let distance =
+                    offset.x * direction.x + offset.y * direction.y + offset.z * direction.z;
+
+                if distance < 0.0 || distance > max_distance {
+                    continue;
+                }
+
+                let closest = Point {
+                    x: origin.x + direction.x * distance,
+                    y: origin.y + direction.y * distance,
+                    z: origin.z + direction.z * distance,
+                };
+
+                if closest.distance_squared(point) <= radius * radius {
+                    candidates.push(Reverse(QueueItem {
+                        distance,
+                        index: *point_index,
+                    }));
+                }
+            }
+
+            for child_index in node.children.iter() {
+                let child = self.nodes.get(*child_index).unwrap();
+
+                if let Some(distance) =
+                    Self::ray_enters_node(child, origin, &direction, max_distance, radius)
+                {
+                    nodes.push(Reverse(QueueItem {
+                        distance,
+                        index: *child_index,
+                    }));
+                }
+            }
+        }
+    }
+
+    // Distance along the ray where it enters the node bounds grown by radius (slab test)
+    fn ray_enters_node(
+        node: &OctreeNode,
+        origin: &Point,
+        direction: &Point,
+        max_distance: f32,
+        radius: f32,
+    ) -> Option<f32> {
+        let (_, min, max) = &node.bounds;
+        let mut enter = 0.0_f32;
+        let mut exit = max_distance;
+
+        for (origin, direction, min, max) in [
+            (origin.x, direction.x, min.x - radius, max.x + radius),
+            (origin.y, direction.y, min.y - radius, max.y + radius),
+            (origin.z, direction.z, min.z - radius, max.z + radius),
+        ] {
+            if direction == 0.0 {
+                if origin < min || origin > max {
+                    return None;
+                }
+
+                continue;
+            }
+
+            let a = (min - origin) / direction;
+            let b = (max - origin) / direction;
+
+            enter = enter.max(a.min(b));
+            exit = exit.min(a.max(b));
+
+            if enter > exit {
+                return None;
+            }
+        }
+
+        Some(enter)
+    }
+}
+
+// Subtree built in parallel before it is moved into the arenas, points keep their Morton code
+struct BuildNode<T> {
+    bounds: (Point, Point, Point),
+    points: Vec<(u64, Point, T)>,
+    children: Vec<BuildNode<T>>,
+}
+
+impl<T: Send> Octree<T> {
+    // Bulk construction: points are sorted by Morton code and subtrees are built in parallel.
+    // The root is a cube around the points, at least MIN_ROOT_SIZE wide.
+    pub fn build_from<P: ToPoint>(
+        points: Vec<(P, T)>,
+        config: OctreeConfig,
+    ) -> Result<Self, SpatialIndexError> {
+        let mut octree = Self::with_config(config);
+        octree.rebuild(points)?;
+
+        Ok(octree)
+    }
+
+    // Replaces all points like clear followed by insert, but builds the tree in bulk like
+    // build_from. The root keeps its bounds and the storage is reused, points outside the root
+    // go through insert so the out of bounds policy applies. Returns the indices of the points
+    // in input order.
+    pub fn rebuild<P: ToPoint>(
+        &mut self,
+        points: Vec<(P, T)>,
+    ) -> Result<Vec<Index>, SpatialIndexError> {
+        self.clear();
+
+        let points = points
+            .into_iter()
+            .map(|(point, data)| (point.to_point(), data))
+            .collect::<Vec<_>>();
+
+        let root = match self.root {
+            Some(root) => root,
+            None => {
+                let (center, min, max) = bounding_cube_of(&points);
+                self.create_root(center, min, max)
+            }
+        };
+
+        let root_node = self.nodes.get(root).unwrap();
+        let bounds = root_node.bounds.clone();
+        let (inside, outside): (Vec<_>, Vec<_>) = points
+            .into_iter()
+            .enumerate()
+            .map(|(i, (point, data))| (point, (i, data)))
+            .partition(|(point, _)| Self::is_point_in_bounds(root_node, point));
+
+        let mut indices = vec![None; inside.len() + outside.len()];
+        let inside = sort_by_morton(&bounds, inside, self.config.max_depth);
+        let tree = Self::build_node(&self.config, bounds, inside, 0, 0);
+
+        // The built tree replaces the empty root
+        let old_root = self.nodes.remove(root).unwrap();
+        self.recycle_node(old_root);
+        self.root = Some(self.attach(tree, None, &mut indices));
+
+        for (point, (i, data)) in outside {
+            indices[i] = Some(self.insert(point, data)?);
+        }
+
+        Ok(indices.into_iter().map(Option::unwrap).collect())
+    }
+
+    // Same subdivision rules as insert, so the result matches repeated insertion. The points
+    // are sorted by Morton code, level is the digit of the code that splits this node.
+    fn build_node<U: Send>(
+        config: &OctreeConfig,
+        bounds: (Point, Point, Point),
+        points: Vec<(u64, Point, U)>,
+        depth: usize,
+        level: usize,
+    ) -> BuildNode<U> {
+        let (_, min, max) = &bounds;
+        let child_size = (max.x - min.x).min(max.y - min.y).min(max.z - min.z) / 2.0;
+
+        if points.len() <= config.max_points
+            || depth >= config.max_depth
+            || child_size < config.min_cell_size
+        {
+            return BuildNode {
+                bounds,
+                points,
+                children: vec![],
+            };
+        }
+
+        // Deeper than the codes reach, the points are sorted again relative to this node
+        let (points, level) = if level == MORTON_BITS {
+            let points = points
+                .into_iter()
+                .map(|(_, point, data)| (point, data))
+                .collect();
+
+            (sort_by_morton(&bounds, points, config.max_depth - depth), 0)
+        } else {
+            (points, level)
+        };
+
+        let count = points.len();
+        let shift = 3 * (MORTON_BITS - 1 - level);
+
+        // Every child is a contiguous range of the sorted points
+        let mut rest = points;
+        let mut ranges = (0..8)
+            .rev()
+            .map(|octant| {
+                let start = rest.partition_point(|(code, _, _)| (code >> shift) & 7 < octant);
+                rest.split_off(start)
+            })
+            .collect::<Vec<_>>();
+        ranges.reverse();
+
+        let children = child_bounds(&bounds).into_iter().zip(ranges);
+
+        let children = if count > PARALLEL_BUILD_THRESHOLD {
+            children
+                .collect::<Vec<_>>()
+                .into_par_iter()
+                .map(|(bounds, points)| {
+                    Self::build_node(config, bounds, points, depth + 1, level + 1)
+                })
+                .collect()
+        } else {
+            children
+                .map(|(bounds, points)| {
+                    Self::build_node(config, bounds, points, depth + 1, level + 1)
+                })
+                .collect()
+        };
+
+        BuildNode {
+            bounds,
+            points: vec![],
+            children,
+        }
+    }
+
+    // Stores a built subtree, the index of the i-th input point is written to indices[i]
+    fn attach(
+        &mut self,
+        node: BuildNode<(usize, T)>,
+        parent: Option<Index>,
+        indices: &mut [Option<Index>],
+    ) -> Index {
+        let index = self.insert_node(parent, node.bounds, node.children.is_empty());
+
+        for (_, point, (i, data)) in node.points {
+            let point_index = self.insert_point(PointWrapper {
+                point,
+                data,
+                leaf: Some(index),
+            });
+            indices[i] = Some(point_index);
+
+            push_counted(
+                &mut self.nodes.get_mut(index).unwrap().points,
+                point_index,
+                &mut self.allocations,
+            );
+        }
+
+        if !node.children.is_empty() {
+            let mut children = take_vec(&mut self.spare_children, &mut self.allocations, 8);
+
+            for child in node.children {
+                children.push(self.attach(child, Some(index), indices));
+            }
+
+            self.nodes.get_mut(index).unwrap().children = children;
+        }
+
+        index
+    }
+}
+
+// Spare node vectors and the allocation count are not saved
+#[cfg(feature = "serde")]
+impl<T: serde::Serialize> Octree<T> {
+    // Compact binary format
+    pub fn to_bytes(&self) -> bincode::Result<Vec<u8>> {
+        bincode::serialize(self)
+    }
+
+    // Human-readable format, e.g. for inspecting a frame's octree
+    pub fn to_json(&self) -> serde_json::Result<String> {
+        serde_json::to_string_pretty(self)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<T: serde::de::DeserializeOwned> Octree<T> {
+    pub fn from_bytes(bytes: &[u8]) -> bincode::Result<Self> {
+        bincode::deserialize(bytes)
+    }
+
+    pub fn from_json(json: &str) -> serde_json::Result<Self> {
+        serde_json::from_str(json)
+    }
+}
+
+impl<T: Send + Sync> SpatialIndex<T> for Octree<T> {
+    fn build(points: Vec<(Point, T)>, config: &IndexConfig) -> Result<Self, SpatialIndexError> {
+        Self::build_from(points, config.octree.clone())
+    }
+
+    fn for_each_point_within_radius(
+        &self,
+        point: &Point,
+        radius: f32,
+        visit: &mut dyn FnMut(&Point, &T),
+    ) {
+        self.points_within_radius(point, radius)
+            .for_each(|(other, data)| visit(other, data));
+    }
+
+    fn k_nearest(&self, point: &Point, k: usize) -> Vec<&T> {
+        Octree::k_nearest(self, point, k)
+    }
+}
+
+fn octant_around(center: &Point, point: &Point) -> usize {
+    let mut octant = 0;
+
+    if point.x >= center.x {
+        octant += 1;
+    }
+
+    if point.y < center.y {
+        octant += 2;
+    }
+
+    if point.z < center.z {
+        octant += 4;
+    }
+
+    octant
+}
+
+// Sorts the points by the octants they fall in on the way down from the bounds, so the points
+// of every descendant are contiguous. Codes are computed for at most levels levels.
+fn sort_by_morton<U: Send>(
+    bounds: &(Point, Point, Point),
+    points: Vec<(Point, U)>,
+    levels: usize,
+) -> Vec<(u64, Point, U)> {
+    let levels = levels.min(MORTON_BITS);
+
+    let mut points = points
+        .into_par_iter()
+        .map(|(point, data)| (octant_path(bounds, &point, levels), point, data))
+        .collect::<Vec<_>>();
+    points.par_sort_unstable_by_key(|(code, _, _)| *code);
+
+    points
+}
+
+// Octant at each level as a 3 bit digit, the first level in the most significant digit of
+// MORTON_BITS digits. Follows child_bounds exactly, unlike morton_code which quantizes.
+fn octant_path(bounds: &(Point, Point, Point), point: &Point, levels: usize) -> u64 {
+    let mut bounds = bounds.clone();
+    let mut code = 0;
+
+    for _ in 0..levels {
+        let octant = octant_around(&bounds.0, point);
+        code = code << 3 | octant as u64;
+        bounds = child_bounds(&bounds)[octant].clone();
+    }
+
+    code << (3 * (MORTON_BITS - levels))
+}
+
+// Interleaves MORTON_BITS bits of each quantized coordinate (z, y, x from the most significant bit)
+pub fn morton_code(point: &Point, min: &Point, max: &Point) -> u64 {
+    let quantize = |value: f32, min: f32, max: f32| {
+        if max > min {
+            (((value - min) / (max - min)) * ((1 << MORTON_BITS) - 1) as f32) as u64
+        } else {
+            0
+        }
+    };
+
+    spread_bits(quantize(point.x, min.x, max.x))
+        | spread_bits(quantize(point.y, min.y, max.y)) << 1
+        | spread_bits(quantize(point.z, min.z, max.z)) << 2
+}
+
+// Inserts two zero bits between each of the lowest 21 bits
+fn spread_bits(value: u64) -> u64 {
+    let mut value = value & 0x1f_ffff;
+    value = (value | value << 32) & 0x1f_0000_0000_ffff;
+    value = (value | value << 16) & 0x1f_0000_ff00_00ff;
+    value = (value | value << 8) & 0x100f_00f0_0f00_f00f;
+    value = (value | value << 4) & 0x10c3_0c30_c30c_30c3;
+    value = (value | value << 2) & 0x1249_2492_4924_9249;
+    value
+}
+
+// Cube around a box, at least MIN_ROOT_SIZE wide and wide enough that doubling it is not lost
+// to float rounding far from the origin
+fn bounding_cube(min: &Point, max: &Point) -> (Point, Point, Point) {
+    let center = Point {
+        x: (min.x + max.x) / 2.0,
+        y: (min.y + max.y) / 2.0,
+        z: (min.z + max.z) / 2.0,
+    };
+    let half = ((max.x - min.x).max(max.y - min.y).max(max.z - min.z) / 2.0)
+        .max(MIN_ROOT_SIZE / 2.0)
+        .max(magnitude(min, max) * 4.0 * f32::EPSILON);
+
+    // Rounding of the center must not leave part of the box outside the cube
+    let low = Point {
+        x: (center.x - half).min(min.x),
+        y: (center.y - half).min(min.y),
+        z: (center.z - half).min(min.z),
+    };
+    let high = Point {
+        x: (center.x + half).max(max.x),
+        y: (center.y + half).max(max.y),
+        z: (center.z + half).max(max.z),
+    };
+
+    (center, low, high)
+}
+
+// Cube around the finite points, or around the origin if there are none
+fn bounding_cube_of<T>(points: &[(Point, T)]) -> (Point, Point, Point) {
+    let mut finite = points
+        .iter()
+        .map(|(point, _)| point)
+        .filter(|point| point.is_finite());
+
+    let (mut min, mut max) = match finite.next() {
+        Some(first) => (first.clone(), first.clone()),
+        None => (Point::ZERO, Point::ZERO),
+    };
+
+    for point in finite {
+        min.x = min.x.min(point.x);
+        min.y = min.y.min(point.y);
+        min.z = min.z.min(point.z);
+        max.x = max.x.max(point.x);
+        max.y = max.y.max(point.y);
+        max.z = max.z.max(point.z);
+    }
+
+    bounding_cube(&min, &max)
+}
+
+// Largest absolute coordinate of a box
+fn magnitude(min: &Point, max: &Point) -> f32 {
+    [min.x, min.y, min.z, max.x, max.y, max.z]
+        .into_iter()
+        .fold(0.0, |magnitude, value| magnitude.max(value.abs()))
+}
+
+// Whether some axis of a box is so thin that doubling it does not change the bounds
+fn is_too_thin(min: &Point, max: &Point) -> bool {
+    let width = (max.x - min.x).min(max.y - min.y).min(max.z - min.z);
+
+    width <= magnitude(min, max) * 4.0 * f32::EPSILON
+}
+
+fn push_counted<V>(vec: &mut Vec<V>, value: V, allocations: &mut usize) {
+    if vec.len() == vec.capacity() {
+        *allocations += 1;
+    }
+
+    vec.push(value);
+}
+
+// Empty vector from the spare ones with room for at least capacity indices
+fn take_vec(spare: &mut Vec<Vec<Index>>, allocations: &mut usize, capacity: usize) -> Vec<Index> {
+    let mut vec = spare.pop().unwrap_or_default();
+
+    if vec.capacity() < capacity {
+        *allocations += 1;
+        vec.reserve(capacity);
     }
 
-    fn subdivide(&mut self, node_index: Index) {
-        let node = self.nodes.get(node_index).unwrap();
-        let (center, min, max) = node.bounds.clone();
-        let mut children = vec![];
+    vec
+}
+
+// Keeps an emptied vector for reuse, vectors without capacity are dropped
+fn recycle(spare: &mut Vec<Vec<Index>>, allocations: &mut usize, mut vec: Vec<Index>) {
+    if vec.capacity() > 0 {
+        vec.clear();
+        push_counted(spare, vec, allocations);
+    }
+}
+
+// Bounds (center, min, max) of the eight children in the order used by subdivide
+fn child_bounds(bounds: &(Point, Point, Point)) -> [(Point, Point, Point); 8] {
+    let (center, min, max) = bounds;
 
+    [
         // Front top left
-        children.push(self.create_node(
+        (
             Point {
                 x: center.x - (center.x - min.x) / 2.0,
                 y: center.y + (max.y - center.y) / 2.0,
@@ -144,10 +1972,9 @@ impl<T> Octree<T> {
                 y: max.y,
                 z: max.z,
             },
-        ));
-
+        ),
         // Front top right
-        children.push(self.create_node(
+        (
             Point {
                 x: center.x + (max.x - center.x) / 2.0,
                 y: center.y + (max.y - center.y) / 2.0,
@@ -163,10 +1990,9 @@ impl<T> Octree<T> {
                 y: max.y,
                 z: max.z,
             },
-        ));
-
+        ),
         // Front bottom left
-        children.push(self.create_node(
+        (
             Point {
                 x: center.x - (center.x - min.x) / 2.0,
                 y: center.y - (center.y - min.y) / 2.0,
@@ -182,10 +2008,9 @@ impl<T> Octree<T> {
                 y: center.y,
                 z: max.z,
             },
-        ));
-
+        ),
         // Front bottom right
-        children.push(self.create_node(
+        (
             Point {
                 x: center.x + (max.x - center.x) / 2.0,
                 y: center.y - (center.y - min.y) / 2.0,
@@ -201,10 +2026,9 @@ impl<T> Octree<T> {
                 y: center.y,
                 z: max.z,
             },
-        ));
-
+        ),
         // Back top left
-        children.push(self.create_node(
+        (
             Point {
                 x: center.x - (center.x - min.x) / 2.0,
                 y: center.y + (max.y - center.y) / 2.0,
@@ -220,10 +2044,9 @@ impl<T> Octree<T> {
                 y: max.y,
                 z: center.z,
             },
-        ));
-
+        ),
         // Back top right
-        children.push(self.create_node(
+        (
             Point {
                 x: center.x + (max.x - center.x) / 2.0,
                 y: center.y + (max.y - center.y) / 2.0,
@@ -239,10 +2062,9 @@ impl<T> Octree<T> {
                 y: max.y,
                 z: center.z,
             },
-        ));
-
+        ),
         // Back bottom left
-        children.push(self.create_node(
+        (
             Point {
                 x: center.x - (center.x - min.x) / 2.0,
                 y: center.y - (center.y - min.y) / 2.0,
@@ -258,10 +2080,9 @@ impl<T> Octree<T> {
                 y: center.y,
                 z: center.z,
             },
-        ));
-
+        ),
         // Back bottom right
-        children.push(self.create_node(
+        (
             Point {
                 x: center.x + (max.x - center.x) / 2.0,
                 y: center.y - (center.y - min.y) / 2.0,
@@ -277,100 +2098,1044 @@ impl<T> Octree<T> {
                 y: center.y,
                 z: center.z,
             },
-        ));
+        ),
+    ]
+}
 
-        let mut mut_node = self.nodes.get_mut(node_index).unwrap();
-        mut_node.children = children.clone();
+#[cfg(test)]
+mod conformance;
 
-        let points = mut_node.points.clone();
-        mut_node.points = vec![];
+#[cfg(test)]
+mod tests {
+    use rand::Rng;
 
-        // Fix points
-        // for child_index in children.iter() {
-        // let child = self.nodes.get_mut(*child_index).unwrap();
+    use super::*;
+    use crate::test_utils::{random_index, random_point, test_rng, SIZE};
 
-        // Assign points to children nodes (closest center)
-        for point_index in points.iter() {
-            let point = &self.points.get(*point_index).unwrap().point;
-            let mut closest_child_index = None;
-            let mut closest_distance = f32::MAX;
+    fn empty_octree() -> Octree<usize> {
+        let mut octree = Octree::new(8);
+        octree.create_root(
+            Point {
+                x: 0.0,
+                y: 0.0,
+                z: 0.0,
+            },
+            Point {
+                x: -SIZE,
+                y: -SIZE,
+                z: -SIZE,
+            },
+            Point {
+                x: SIZE,
+                y: SIZE,
+                z: SIZE,
+            },
+        );
 
-            for child_index in children.iter() {
-                let child = self.nodes.get(*child_index).unwrap();
-                let distance = (child.bounds.0.x - point.x).powi(2)
-                    + (child.bounds.0.y - point.y).powi(2)
-                    + (child.bounds.0.z - point.z).powi(2);
+        octree
+    }
+
+    fn random_octree(rng: &mut impl Rng, count: usize) -> (Octree<usize>, Vec<Point>) {
+        random_index(rng, count, random_point, |points| {
+            let mut octree = empty_octree();
+
+            for (point, i) in points {
+                octree.insert(point, i).unwrap();
+            }
+
+            octree
+        })
+    }
+
+    fn sorted<'a>(iter: impl Iterator<Item = (&'a Point, &'a usize)>) -> Vec<usize> {
+        let mut result = iter.map(|(_, data)| *data).collect::<Vec<_>>();
+        result.sort();
+        result
+    }
+
+    #[test]
+    fn query_aabb_matches_brute_force() {
+        let mut rng = test_rng();
+        let (octree, points) = random_octree(&mut rng, 5000);
+
+        for _ in 0..100 {
+            let (a, b) = (random_point(&mut rng), random_point(&mut rng));
+            let min = Point {
+                x: a.x.min(b.x),
+                y: a.y.min(b.y),
+                z: a.z.min(b.z),
+            };
+            let max = Point {
+                x: a.x.max(b.x),
+                y: a.y.max(b.y),
+                z: a.z.max(b.z),
+            };
+
+            let expected = (0..points.len())
+                .filter(|i| {
+                    let point = &points[*i];
+
+                    point.x >= min.x
+                        && point.x <= max.x
+                        && point.y >= min.y
+                        && point.y <= max.y
+                        && point.z >= min.z
+                        && point.z <= max.z
+                })
+                .collect::<Vec<_>>();
+
+            assert_eq!(sorted(octree.query_aabb(&min, &max)), expected);
+        }
+    }
+
+    #[test]
+    fn query_frustum_matches_brute_force() {
+        let mut rng = test_rng();
+        let (octree, points) = random_octree(&mut rng, 5000);
+
+        for _ in 0..100 {
+            // Random convex volume: planes through random points facing a common center
+            let center = random_point(&mut rng);
+            let planes = (0..6)
+                .map(|_| {
+                    let on_plane = random_point(&mut rng);
+                    let normal = Point {
+                        x: center.x - on_plane.x,
+                        y: center.y - on_plane.y,
+                        z: center.z - on_plane.z,
+                    };
+                    let distance =
+                        -(normal.x * on_plane.x + normal.y * on_plane.y + normal.z * on_plane.z);
+
+                    Plane { normal, distance }
+                })
+                .collect::<Vec<_>>();
+
+            let expected = (0..points.len())
+                .filter(|i| {
+                    planes
+                        .iter()
+                        .all(|plane| plane.signed_distance(&points[*i]) >= 0.0)
+                })
+                .collect::<Vec<_>>();
 
-                if distance < closest_distance {
-                    closest_child_index = Some(child_index);
-                    closest_distance = distance;
+            assert_eq!(sorted(octree.query_frustum(&planes)), expected);
+        }
+    }
+
+    #[test]
+    fn raycast_matches_brute_force() {
+        let mut rng = test_rng();
+        let (octree, points) = random_octree(&mut rng, 5000);
+        let radius = 2.0;
+        let max_distance = SIZE;
+
+        for _ in 0..100 {
+            let origin = random_point(&mut rng);
+            let direction = random_point(&mut rng);
+            let length = direction
+                .distance_squared(&Point {
+                    x: 0.0,
+                    y: 0.0,
+                    z: 0.0,
+                })
+                .sqrt();
+
+            let mut expected = points
+                .iter()
+                .enumerate()
+                .filter_map(|(i, point)| {
+                    let distance = ((point.x - origin.x) * direction.x
+                        + (point.y - origin.y) * direction.y
+                        + (point.z - origin.z) * direction.z)
+                        / length;
+
+                    let closest = Point {
+                        x: origin.x + direction.x / length * distance,
+                        y: origin.y + direction.y / length * distance,
+                        z: origin.z + direction.z / length * distance,
+                    };
+
+                    if (0.0..=max_distance).contains(&distance)
+                        && closest.distance_squared(point) <= radius * radius
+                    {
+                        Some((distance, i))
+                    } else {
+                        None
+                    }
+                })
+                .collect::<Vec<_>>();
+
+            expected.sort_by(|a, b| a.0.total_cmp(&b.0));
+
+            let hits = octree.raycast_all(&origin, &direction, max_distance, radius);
+
+            assert_eq!(
+                hits.iter().map(|hit| *hit.data).collect::<Vec<_>>(),
+                expected.iter().map(|(_, i)| *i).collect::<Vec<_>>()
+            );
+            assert_eq!(
+                octree
+                    .raycast(&origin, &direction, max_distance, radius)
+                    .map(|hit| *hit.data),
+                expected.first().map(|(_, i)| *i)
+            );
+        }
+    }
+
+    // Points in a cube twice the size of the root
+    fn random_points_around(rng: &mut impl Rng, count: usize) -> Vec<Point> {
+        (0..count)
+            .map(|_| {
+                let point = random_point(rng);
+
+                Point {
+                    x: point.x * 2.0,
+                    y: point.y * 2.0,
+                    z: point.z * 2.0,
                 }
+            })
+            .collect()
+    }
+
+    fn assert_points_in_leaf_bounds<T>(octree: &Octree<T>) {
+        for (index, point) in octree.points.iter() {
+            let leaf = point.leaf.unwrap();
+            let node = octree.nodes.get(leaf).unwrap();
+
+            assert!(node.children.is_empty());
+            assert!(node.points.contains(&index));
+
+            if Some(leaf) == octree.overflow {
+                let root = octree.nodes.get(octree.root.unwrap()).unwrap();
+                assert!(!Octree::<T>::is_point_in_bounds(root, &point.point));
+            } else {
+                assert!(Octree::<T>::is_point_in_bounds(node, &point.point));
+            }
+        }
+    }
+
+    #[test]
+    fn points_lie_inside_their_leaf() {
+        let mut rng = test_rng();
+        for policy in [OutOfBoundsPolicy::Grow, OutOfBoundsPolicy::Overflow] {
+            let mut octree = empty_octree();
+            octree.config.out_of_bounds = policy;
+
+            let points = random_points_around(&mut rng, 3000);
+            let indices = points
+                .iter()
+                .enumerate()
+                .map(|(i, point)| octree.insert(point.clone(), i).unwrap())
+                .collect::<Vec<_>>();
+
+            assert_points_in_leaf_bounds(&octree);
+
+            for index in indices.iter() {
+                octree.update(*index, random_point(&mut rng)).unwrap();
             }
 
-            let closest_child = self.nodes.get_mut(*closest_child_index.unwrap()).unwrap();
-            closest_child.points.push(*point_index);
+            assert_points_in_leaf_bounds(&octree);
+
+            let center = random_point(&mut rng);
+            let expected = (0..points.len()).collect::<Vec<_>>();
+
+            let mut found = octree
+                .find_within_radius(&center, SIZE * 4.0)
+                .into_iter()
+                .copied()
+                .collect::<Vec<_>>();
+            found.sort();
+
+            assert_eq!(found, expected);
         }
+    }
+
+    #[test]
+    fn out_of_bounds_policies() {
+        let mut rng = test_rng();
+        let outside = Point {
+            x: SIZE * 3.0,
+            y: -SIZE * 5.0,
+            z: 0.0,
+        };
 
-        // for point_index in points.iter() {
-        //     if Octree::<T>::is_point_in_bounds(
-        //         &child,
-        //         &self.points.get(*point_index).unwrap().point,
-        //     ) {
-        //         child.points.push(*point_index);
-        //     }
-        // }
-        // }
+        let mut octree = empty_octree();
+        octree.config.out_of_bounds = OutOfBoundsPolicy::Reject;
+        assert!(octree.insert(outside.clone(), 0).is_err());
+        assert_eq!(octree.points.len(), 0);
+
+        let index = octree.insert(random_point(&mut rng), 1).unwrap();
+        assert!(octree.update(index, outside.clone()).is_err());
+        assert!(octree.find_within_radius(&outside, 1.0).is_empty());
+        octree.update(index, random_point(&mut rng)).unwrap();
+        assert_points_in_leaf_bounds(&octree);
+
+        let mut octree = empty_octree();
+        octree.config.out_of_bounds = OutOfBoundsPolicy::Overflow;
+        octree.insert(outside.clone(), 0).unwrap();
+        assert_eq!(octree.find_within_radius(&outside, 1.0), vec![&0]);
+        assert_eq!(octree.k_nearest(&outside, 1), vec![&0]);
+
+        let mut octree = empty_octree();
+        octree.config.out_of_bounds = OutOfBoundsPolicy::Grow;
+        octree.insert(outside.clone(), 0).unwrap();
+        assert!(octree.overflow.is_none());
+        assert_eq!(octree.find_neighbors(&outside), vec![&0]);
+        assert_points_in_leaf_bounds(&octree);
     }
 
-    fn create_node(&mut self, center: Point, min: Point, max: Point) -> Index {
-        let node = OctreeNode {
-            bounds: (center, min, max),
-            children: vec![],
-            points: vec![],
+    #[test]
+    fn refused_updates_leave_the_octree_unchanged() {
+        let mut rng = test_rng();
+        let outside = Point {
+            x: SIZE * 3.0,
+            y: 0.0,
+            z: 0.0,
+        };
+        let not_finite = Point {
+            x: f32::NAN,
+            y: 0.0,
+            z: 0.0,
         };
 
-        self.nodes.insert(node)
+        for (policy, refused) in [
+            (OutOfBoundsPolicy::Reject, outside),
+            (OutOfBoundsPolicy::Grow, not_finite),
+        ] {
+            let (mut octree, points) = random_octree(&mut rng, 100);
+            octree.config.out_of_bounds = policy;
+            let index = octree.points.iter().next().unwrap().0;
+            let point = octree.points.get(index).unwrap().point.clone();
+            let data = *octree.get(index).unwrap();
+
+            assert!(octree.update(index, refused.clone()).is_err());
+
+            // Still stored in its leaf at the old position
+            assert_eq!(
+                octree
+                    .points
+                    .get(index)
+                    .unwrap()
+                    .point
+                    .distance_squared(&point),
+                0.0
+            );
+            assert_eq!(octree.stats().points, points.len());
+            assert!(octree.find_within_radius(&point, 0.0).contains(&&data));
+            assert_points_in_leaf_bounds(&octree);
+        }
     }
 
-    pub fn find_neighbors(&self, point: &Point) -> Vec<&T> {
-        let mut neighbors = vec![];
+    #[test]
+    fn grow_keeps_boundary_points_reachable() {
+        let mut octree = empty_octree();
+        octree.config.out_of_bounds = OutOfBoundsPolicy::Grow;
 
-        if let Some(root) = self.root {
-            let mut node_index = root;
-            let mut node = self.nodes.get(node_index).unwrap();
+        // On the max x face of the root, which becomes the center plane after growing
+        let boundary = Point {
+            x: SIZE,
+            y: -SIZE,
+            z: -SIZE,
+        };
+        octree.insert(boundary.clone(), 0).unwrap();
+        octree
+            .insert(
+                Point {
+                    x: -SIZE,
+                    y: -SIZE,
+                    z: -SIZE * 1.5,
+                },
+                1,
+            )
+            .unwrap();
 
-            while node.children.len() > 0 {
-                // Find the child that contains the point (closest center)
-                let mut closest_child_index = None;
-                let mut closest_distance = f32::MAX;
+        assert!(octree.find_neighbors(&boundary).contains(&&0));
+        assert_points_in_leaf_bounds(&octree);
+    }
 
-                for child_index in node.children.iter() {
-                    let child = self.nodes.get(*child_index).unwrap();
-                    let distance = (child.bounds.0.x - point.x).powi(2)
-                        + (child.bounds.0.y - point.y).powi(2)
-                        + (child.bounds.0.z - point.z).powi(2);
+    #[test]
+    fn first_insert_creates_a_root() {
+        let mut rng = test_rng();
 
-                    if distance < closest_distance {
-                        closest_child_index = Some(child_index);
-                        closest_distance = distance;
-                    }
+        for out_of_bounds in [
+            OutOfBoundsPolicy::Grow,
+            OutOfBoundsPolicy::Overflow,
+            OutOfBoundsPolicy::Reject,
+        ] {
+            let mut octree = Octree::with_config(OctreeConfig {
+                max_points: 8,
+                out_of_bounds,
+                ..Default::default()
+            });
+            let point = random_point(&mut rng);
+
+            octree.insert(point.clone(), 0).unwrap();
+
+            assert!(octree.root.is_some());
+            assert_eq!(octree.find_within_radius(&point, 0.0), vec![&0]);
+            assert_points_in_leaf_bounds(&octree);
+        }
+    }
+
+    #[test]
+    fn grow_to_a_far_point_keeps_the_old_root() {
+        let mut rng = test_rng();
+        let (mut octree, points) = random_octree(&mut rng, 100);
+        octree.config.out_of_bounds = OutOfBoundsPolicy::Grow;
+        let old_root = octree.root.unwrap();
+        let far = Point {
+            x: SIZE * 1000.0,
+            y: -SIZE * 300.0,
+            z: SIZE * 10.0,
+        };
+
+        octree.insert(far.clone(), points.len()).unwrap();
+
+        // Grown around the old root instead of being rebuilt
+        assert_ne!(octree.root, Some(old_root));
+        assert!(octree.nodes.contains(old_root));
+        assert_eq!(octree.k_nearest(&far, 1), vec![&points.len()]);
+
+        for (i, point) in points.iter().enumerate() {
+            assert!(octree.find_within_radius(point, 0.0).contains(&&i));
+        }
+
+        assert_points_in_leaf_bounds(&octree);
+    }
+
+    #[test]
+    fn grow_from_degenerate_inputs() {
+        let config = OctreeConfig {
+            max_points: 2,
+            out_of_bounds: OutOfBoundsPolicy::Grow,
+            ..Default::default()
+        };
+        let collinear = (0..10)
+            .map(|i| {
+                (
+                    Point {
+                        x: i as f32,
+                        y: 0.0,
+                        z: 0.0,
+                    },
+                    i,
+                )
+            })
+            .collect::<Vec<_>>();
+
+        // A single point, no points and collinear points give a root without width
+        for points in [vec![(Point::ZERO, 0)], vec![], collinear] {
+            let count = points.len();
+            let mut octree = Octree::build_from(points, config.clone()).unwrap();
+            let outside = Point {
+                x: 5.0,
+                y: 1e6,
+                z: -3.0,
+            };
+
+            octree.insert(outside.clone(), count).unwrap();
+
+            assert_eq!(octree.points.len(), count + 1);
+            assert_eq!(octree.k_nearest(&outside, 1), vec![&count]);
+            assert_points_in_leaf_bounds(&octree);
+        }
+
+        // A root created without width grows as well
+        let mut octree = Octree::with_config(config);
+        octree.create_root(Point::ZERO, Point::ZERO, Point::ZERO);
+        octree.insert(Point::ZERO, 0).unwrap();
+        octree
+            .insert(
+                Point {
+                    x: 5.0,
+                    y: 0.0,
+                    z: 0.0,
+                },
+                1,
+            )
+            .unwrap();
+
+        assert_eq!(octree.find_within_radius(&Point::ZERO, 10.0).len(), 2);
+        assert_points_in_leaf_bounds(&octree);
+    }
+
+    #[test]
+    fn subdivision_respects_depth_and_cell_size() {
+        let mut rng = test_rng();
+        let mut octree = empty_octree();
+        octree.config.max_depth = 4;
+
+        // Coincident points can not be separated by subdividing
+        for i in 0..100 {
+            octree
+                .insert(
+                    Point {
+                        x: 1.0,
+                        y: 2.0,
+                        z: 3.0,
+                    },
+                    i,
+                )
+                .unwrap();
+        }
+
+        let max_depth = octree
+            .nodes
+            .iter()
+            .map(|(index, _)| octree.depth(index))
+            .max()
+            .unwrap();
+
+        assert_eq!(max_depth, 4);
+        assert_eq!(
+            octree
+                .find_neighbors(&Point {
+                    x: 1.0,
+                    y: 2.0,
+                    z: 3.0
+                })
+                .len(),
+            100
+        );
+
+        let mut octree = empty_octree();
+        octree.config.min_cell_size = SIZE / 2.0;
+
+        for i in 0..1000 {
+            octree.insert(random_point(&mut rng), i).unwrap();
+        }
+
+        for (_, node) in octree.nodes.iter() {
+            let (_, min, max) = &node.bounds;
+            assert!(max.x - min.x >= SIZE / 2.0);
+        }
+    }
+
+    #[test]
+    fn build_from_deeper_than_morton_codes() {
+        let config = OctreeConfig {
+            max_points: 1,
+            max_depth: 40,
+            ..Default::default()
+        };
+        // Close enough to the origin that only levels past MORTON_BITS separate them
+        let points = (0..20)
+            .map(|i| {
+                (
+                    Point {
+                        x: i as f32 * 1e-9,
+                        y: 0.0,
+                        z: 0.0,
+                    },
+                    i,
+                )
+            })
+            .chain([(
+                Point {
+                    x: 0.5,
+                    y: 0.5,
+                    z: 0.5,
+                },
+                20,
+            )])
+            .collect::<Vec<_>>();
+
+        let octree = Octree::build_from(points.clone(), config).unwrap();
+
+        assert!(octree.stats().depth > MORTON_BITS);
+        assert_points_in_leaf_bounds(&octree);
+
+        for (point, i) in points.iter() {
+            assert_eq!(octree.find_within_radius(point, 0.0), vec![i]);
+        }
+    }
+
+    #[test]
+    fn build_from_matches_insert() {
+        let mut rng = test_rng();
+        let points = (0..20000)
+            .map(|_| random_point(&mut rng))
+            .collect::<Vec<_>>();
+        let config = OctreeConfig {
+            max_points: 16,
+            ..Default::default()
+        };
+
+        let mut inserted = Octree::with_config(config.clone());
+        inserted.create_root(
+            Point {
+                x: 0.0,
+                y: 0.0,
+                z: 0.0,
+            },
+            Point {
+                x: -SIZE,
+                y: -SIZE,
+                z: -SIZE,
+            },
+            Point {
+                x: SIZE,
+                y: SIZE,
+                z: SIZE,
+            },
+        );
+
+        for (i, point) in points.iter().enumerate() {
+            inserted.insert(point.clone(), i).unwrap();
+        }
+
+        let built = Octree::build_from(
+            points
+                .iter()
+                .cloned()
+                .enumerate()
+                .map(|(i, point)| (point, i))
+                .collect(),
+            config,
+        )
+        .unwrap();
+
+        assert_eq!(built.points.len(), points.len());
+        assert_points_in_leaf_bounds(&built);
+
+        for (_, node) in built.nodes.iter() {
+            assert!(node.children.is_empty() || node.points.is_empty());
+            assert!(!node.children.is_empty() || node.points.len() <= 16);
+        }
+
+        for _ in 0..100 {
+            let center = random_point(&mut rng);
+            let radius = rng.gen::<f32>() * SIZE / 4.0;
+
+            let mut expected = inserted.find_within_radius(&center, radius);
+            let mut found = built.find_within_radius(&center, radius);
+            expected.sort();
+            found.sort();
+
+            assert_eq!(found, expected);
+            assert_eq!(
+                built.k_nearest(&center, 10),
+                inserted.k_nearest(&center, 10)
+            );
+        }
+    }
+
+    #[test]
+    fn for_each_neighbor_matches_brute_force() {
+        let mut rng = test_rng();
+        let mut octree = empty_octree();
+        let points = random_points_around(&mut rng, 5000);
+
+        for (i, point) in points.iter().enumerate() {
+            octree.insert(point.clone(), i).unwrap();
+        }
 
-                    // if Octree::<T>::is_point_in_bounds(&child, point) {
-                    //     node_index = *child_index;
-                    //     node = self.nodes.get(node_index).unwrap();
-                    //     break;
-                    // }
+        for _ in 0..100 {
+            let center = random_point(&mut rng);
+            let radius = rng.gen::<f32>() * SIZE / 2.0;
+
+            let expected = (0..points.len())
+                .filter(|i| points[*i].distance_squared(&center) <= radius * radius)
+                .collect::<Vec<_>>();
+
+            let mut found = vec![];
+            octree.for_each_neighbor(&center, radius, |i| found.push(*i));
+            found.sort();
+
+            assert_eq!(found, expected);
+        }
+    }
+
+    #[test]
+    fn approximation_matches_summaries() {
+        let mut rng = test_rng();
+        let mut octree = empty_octree();
+        // All inside the root, the overflow bucket is never summarized
+        let points = (0..5000)
+            .map(|_| random_point(&mut rng))
+            .collect::<Vec<_>>();
+
+        for (i, point) in points.iter().enumerate() {
+            octree.insert(point.clone(), i).unwrap();
+        }
+
+        // Index as the velocity of every point
+        let velocity = |i: &usize| Point {
+            x: *i as f32,
+            y: 1.0,
+            z: 0.0,
+        };
+        octree.update_summaries(velocity);
+
+        let root = octree.nodes.get(octree.root.unwrap()).unwrap();
+        assert_eq!(root.summary.as_ref().unwrap().count, points.len());
+
+        // theta = 0 never summarizes
+        for _ in 0..20 {
+            let center = random_point(&mut rng);
+            let radius = rng.gen::<f32>() * SIZE / 2.0;
+
+            let expected = (0..points.len())
+                .filter(|i| points[*i].distance_squared(&center) <= radius * radius)
+                .collect::<Vec<_>>();
+
+            let mut found = vec![];
+            octree.for_each_approximate(&center, radius, 0.0, |neighbor| match neighbor {
+                Approximation::Exact(i) => found.push(*i),
+                Approximation::Group(_) => panic!("summarized with theta = 0"),
+            });
+            found.sort();
+
+            assert_eq!(found, expected);
+        }
+
+        // With a radius covering everything the groups and points add up to all points
+        let mut visits = 0;
+        let mut count = 0;
+        let mut position_sum = Point::ZERO;
+        let mut velocity_sum = Point::ZERO;
+
+        octree.for_each_approximate(random_point(&mut rng), SIZE * 100.0, 0.5, |neighbor| {
+            visits += 1;
+
+            match neighbor {
+                Approximation::Exact(i) => {
+                    count += 1;
+                    position_sum.add_scaled(&points[*i], 1.0);
+                    velocity_sum.add_scaled(&velocity(i), 1.0);
+                }
+                Approximation::Group(summary) => {
+                    count += summary.count;
+                    position_sum.add_scaled(&summary.center_of_mass, summary.count as f32);
+                    velocity_sum.add_scaled(&summary.velocity_sum, 1.0);
+                }
+            }
+        });
+
+        let mut expected_position_sum = Point::ZERO;
+        points
+            .iter()
+            .for_each(|point| expected_position_sum.add_scaled(point, 1.0));
+        let expected_velocity_x = (points.len() * (points.len() - 1) / 2) as f32;
+
+        assert!(visits < points.len() / 2);
+        assert_eq!(count, points.len());
+        assert!(position_sum.distance_squared(&expected_position_sum).sqrt() < SIZE);
+        assert!((velocity_sum.x - expected_velocity_x).abs() < expected_velocity_x * 1e-4);
+        assert_eq!(velocity_sum.y, points.len() as f32);
+    }
+
+    #[test]
+    fn approximation_never_summarizes_the_query_point() {
+        let mut rng = test_rng();
+        let (mut octree, points) = random_octree(&mut rng, 2000);
+        octree.update_summaries(|_| Point::ZERO);
+
+        for (i, point) in points.iter().enumerate().take(100) {
+            let mut exact = vec![];
+            let mut count = 0;
+
+            octree.for_each_approximate(point, SIZE * 100.0, 2.0, |neighbor| match neighbor {
+                Approximation::Exact(other) => {
+                    exact.push(*other);
+                    count += 1;
                 }
+                Approximation::Group(summary) => count += summary.count,
+            });
+
+            assert!(exact.contains(&i));
+            assert_eq!(count, points.len());
+        }
+    }
 
-                node = self.nodes.get(*closest_child_index.unwrap()).unwrap();
+    #[test]
+    fn stats_match_nodes() {
+        let mut rng = test_rng();
+        let mut octree = empty_octree();
+        let points = random_points_around(&mut rng, 5000);
+
+        for (i, point) in points.iter().enumerate() {
+            octree.insert(point.clone(), i).unwrap();
+        }
+
+        let nodes = octree.nodes_with_depth().collect::<Vec<_>>();
+
+        assert_eq!(nodes.len(), octree.nodes.len());
+
+        for (index, _, depth) in nodes.iter() {
+            assert_eq!(*depth, octree.depth(*index));
+        }
+
+        let stats = octree.stats();
+        let leaves = octree
+            .nodes
+            .iter()
+            .filter(|(_, node)| node.children.is_empty())
+            .collect::<Vec<_>>();
+
+        assert_eq!(stats.nodes, octree.nodes.len());
+        assert_eq!(stats.leaves, leaves.len());
+        assert_eq!(stats.leaves, octree.leaves_with_depth().count());
+        assert_eq!(stats.points, points.len());
+        assert_eq!(stats.bytes_held, octree.bytes_held());
+        assert_eq!(
+            stats.depth,
+            leaves
+                .iter()
+                .map(|(index, _)| octree.depth(*index))
+                .max()
+                .unwrap()
+        );
+        assert_eq!(stats.points_per_leaf.iter().sum::<usize>(), stats.leaves);
+        assert_eq!(
+            stats
+                .points_per_leaf
+                .iter()
+                .enumerate()
+                .map(|(count, leaves)| count * leaves)
+                .sum::<usize>(),
+            points.len()
+        );
+        assert_eq!(
+            stats.empty_leaves(),
+            leaves
+                .iter()
+                .filter(|(_, node)| node.points.is_empty())
+                .count()
+        );
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn serialization_round_trip() {
+        let mut rng = test_rng();
+        let mut octree = empty_octree();
+        let points = random_points_around(&mut rng, 5000);
+
+        let indices = points
+            .iter()
+            .enumerate()
+            .map(|(i, point)| octree.insert(point.clone(), i).unwrap())
+            .collect::<Vec<_>>();
+
+        // Leave free slots in the arenas
+        for index in indices.iter().step_by(3) {
+            octree.remove(*index);
+        }
+
+        let from_bytes = Octree::<usize>::from_bytes(&octree.to_bytes().unwrap()).unwrap();
+        let from_json = Octree::<usize>::from_json(&octree.to_json().unwrap()).unwrap();
+
+        for loaded in [from_bytes, from_json] {
+            assert_eq!(loaded.config, octree.config);
+            assert_eq!(loaded.stats().points, octree.stats().points);
+            assert_eq!(loaded.nodes.len(), octree.nodes.len());
+
+            // Indices stay valid
+            for (i, index) in indices.iter().enumerate() {
+                assert_eq!(
+                    loaded.get(*index),
+                    octree.get(*index).filter(|data| **data == i)
+                );
             }
 
-            for point_index in node.points.iter() {
-                let neighbor = self.points.get(*point_index).unwrap();
+            for _ in 0..20 {
+                let center = random_point(&mut rng);
+                let radius = rng.gen::<f32>() * SIZE;
+                let max = Point {
+                    x: center.x + radius,
+                    y: center.y + radius,
+                    z: center.z + radius,
+                };
 
-                neighbors.push(&neighbor.data);
+                assert_eq!(
+                    loaded.find_within_radius(&center, radius),
+                    octree.find_within_radius(&center, radius)
+                );
+                assert_eq!(loaded.k_nearest(&center, 10), octree.k_nearest(&center, 10));
+                assert_eq!(
+                    sorted(loaded.query_aabb(&center, &max)),
+                    sorted(octree.query_aabb(&center, &max))
+                );
             }
         }
-        neighbors
+    }
+
+    #[test]
+    fn clear_reuses_storage() {
+        let mut rng = test_rng();
+        let mut octree = empty_octree();
+        let points = random_points_around(&mut rng, 5000);
+
+        let fill = |octree: &mut Octree<usize>| {
+            for (i, point) in points.iter().enumerate() {
+                octree.insert(point.clone(), i).unwrap();
+            }
+        };
+
+        fill(&mut octree);
+        octree.clear();
+
+        assert_eq!(octree.points.len(), 0);
+        assert_eq!(octree.nodes.len(), 2);
+        assert!(octree
+            .find_within_radius(random_point(&mut rng), SIZE * 4.0)
+            .is_empty());
+
+        fill(&mut octree);
+
+        let allocations = octree.allocations();
+        let bytes_held = octree.bytes_held();
+
+        // Refilling with the same points needs no new storage
+        for _ in 0..3 {
+            octree.clear();
+            fill(&mut octree);
+
+            assert_eq!(octree.allocations(), allocations);
+            assert_eq!(octree.bytes_held(), bytes_held);
+        }
+
+        assert_points_in_leaf_bounds(&octree);
+
+        let center = random_point(&mut rng);
+        let expected = (0..points.len())
+            .filter(|i| points[*i].distance_squared(&center) <= SIZE * SIZE)
+            .collect::<Vec<_>>();
+
+        let mut found = octree
+            .find_within_radius(&center, SIZE)
+            .into_iter()
+            .copied()
+            .collect::<Vec<_>>();
+        found.sort();
+
+        assert_eq!(found, expected);
+    }
+
+    #[test]
+    fn rebuild_reuses_storage() {
+        let mut rng = test_rng();
+        let mut octree = empty_octree();
+        octree.config.out_of_bounds = OutOfBoundsPolicy::Overflow;
+
+        // Some points are outside the root and end up in the overflow bucket
+        let points = random_points_around(&mut rng, 5000);
+        let input = || points.iter().cloned().zip(0..).collect::<Vec<_>>();
+
+        let indices = octree.rebuild(input()).unwrap();
+        octree.rebuild(input()).unwrap();
+
+        let allocations = octree.allocations();
+        let bytes_held = octree.bytes_held();
+
+        for _ in 0..3 {
+            let indices = octree.rebuild(input()).unwrap();
+
+            assert_eq!(octree.allocations(), allocations);
+            assert_eq!(octree.bytes_held(), bytes_held);
+
+            for (i, index) in indices.into_iter().enumerate() {
+                assert_eq!(octree.get(index), Some(&i));
+            }
+        }
+
+        assert_eq!(indices.len(), points.len());
+        assert!(octree.overflow.is_some());
+        assert_points_in_leaf_bounds(&octree);
+
+        let center = random_point(&mut rng);
+        let expected = (0..points.len())
+            .filter(|i| points[*i].distance_squared(&center) <= SIZE * SIZE)
+            .collect::<Vec<_>>();
+
+        assert_eq!(sorted(octree.points_within_radius(&center, SIZE)), expected);
+    }
+
+    #[test]
+    fn vector_types_give_same_results() {
+        let mut rng = test_rng();
+        let points = (0..500).map(|_| random_point(&mut rng)).collect::<Vec<_>>();
+        let arrays = points
+            .iter()
+            .map(|point| [point.x, point.y, point.z])
+            .collect::<Vec<_>>();
+        let vectors = points
+            .iter()
+            .map(|point| nalgebra::Vector3::new(point.x, point.y, point.z))
+            .collect::<Vec<_>>();
+
+        let mut from_points = empty_octree();
+        let mut from_arrays = Octree::new(8);
+        from_arrays.create_root([0.0; 3], [-SIZE; 3], [SIZE; 3]);
+        let mut from_vectors = Octree::new(8);
+        from_vectors.create_root(
+            nalgebra::Vector3::zeros(),
+            nalgebra::Vector3::from_element(-SIZE),
+            nalgebra::Vector3::from_element(SIZE),
+        );
+
+        for i in 0..points.len() {
+            from_points.insert(&points[i], i).unwrap();
+            from_arrays.insert(arrays[i], i).unwrap();
+            from_vectors.insert(vectors[i], i).unwrap();
+        }
+
+        let built = Octree::build_from(
+            arrays.iter().copied().zip(0..).collect(),
+            Default::default(),
+        )
+        .unwrap();
+
+        for _ in 0..20 {
+            let center = random_point(&mut rng);
+            let array = [center.x, center.y, center.z];
+            let vector = nalgebra::Vector3::new(center.x, center.y, center.z);
+
+            let expected = sorted(from_points.points_within_radius(&center, SIZE / 2.0));
+            assert_eq!(
+                sorted(from_arrays.points_within_radius(array, SIZE / 2.0)),
+                expected
+            );
+            assert_eq!(
+                sorted(from_vectors.points_within_radius(vector, SIZE / 2.0)),
+                expected
+            );
+            assert_eq!(
+                sorted(built.points_within_radius(array, SIZE / 2.0)),
+                expected
+            );
+
+            let expected = from_points.k_nearest(&center, 10);
+            assert_eq!(from_arrays.k_nearest(array, 10), expected);
+            assert_eq!(from_vectors.k_nearest(vector, 10), expected);
+        }
+    }
+
+    #[cfg(feature = "glam")]
+    #[test]
+    fn glam_vectors_give_same_results() {
+        let mut rng = test_rng();
+        let (mut octree, _) = random_octree(&mut rng, 500);
+        let center = random_point(&mut rng);
+        let vector = glam::Vec3::new(center.x, center.y, center.z);
+
+        assert_eq!(
+            sorted(octree.points_within_radius(vector, SIZE / 2.0)),
+            sorted(octree.points_within_radius(&center, SIZE / 2.0))
+        );
+        assert_eq!(octree.k_nearest(vector, 10), octree.k_nearest(&center, 10));
+
+        let (index, _) = octree
+            .points
+            .iter()
+            .find(|(_, point)| point.data == 0)
+            .unwrap();
+        octree.update(index, glam::Vec3::ZERO).unwrap();
+
+        assert!(octree
+            .find_within_radius(glam::Vec3::ZERO, 0.0)
+            .contains(&&0));
     }
 }
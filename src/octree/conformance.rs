@@ -0,0 +1,360 @@
+// Property-based tests checking every query against a brute-force scan over random point clouds
+use proptest::prelude::*;
+
+use super::*;
+use crate::test_utils::SIZE;
+
+fn point(x: f32, y: f32, z: f32) -> Point {
+    Point { x, y, z }
+}
+
+fn point_in(min: f32, max: f32) -> impl Strategy<Value = Point> {
+    (min..=max, min..=max, min..=max).prop_map(|(x, y, z)| point(x, y, z))
+}
+
+// Uniform, partly outside the root bounds
+fn uniform() -> impl Strategy<Value = Vec<Point>> {
+    prop::collection::vec(point_in(-SIZE * 1.5, SIZE * 1.5), 0..300)
+}
+
+// Dense blobs around a few centers
+fn clustered() -> impl Strategy<Value = Vec<Point>> {
+    prop::collection::vec(point_in(-SIZE, SIZE), 1..4).prop_flat_map(|centers| {
+        let count = centers.len();
+
+        prop::collection::vec((0..count, point_in(-1.0, 1.0)), 0..300).prop_map(move |offsets| {
+            offsets
+                .into_iter()
+                .map(|(i, offset)| {
+                    point(
+                        centers[i].x + offset.x,
+                        centers[i].y + offset.y,
+                        centers[i].z + offset.z,
+                    )
+                })
+                .collect()
+        })
+    })
+}
+
+// Coincident points, points on a line and points on a plane
+fn degenerate() -> impl Strategy<Value = Vec<Point>> {
+    prop_oneof![
+        (point_in(-SIZE, SIZE), 0..100usize).prop_map(|(point, count)| vec![point; count]),
+        prop::collection::vec(-SIZE..=SIZE, 0..200)
+            .prop_map(|xs| xs.into_iter().map(|x| point(x, 1.0, -1.0)).collect()),
+        prop::collection::vec((-SIZE..=SIZE, -SIZE..=SIZE), 0..200)
+            .prop_map(|xys| xys.into_iter().map(|(x, y)| point(x, y, 0.0)).collect()),
+    ]
+}
+
+// Coordinates on the root faces and the split planes of the first levels, or just next to them
+fn boundary() -> impl Strategy<Value = Vec<Point>> {
+    let values = [-SIZE, -SIZE / 2.0, 0.0, SIZE / 2.0, SIZE]
+        .into_iter()
+        .flat_map(|value: f32| [value, value - value.abs().max(1.0) * 1e-6, value + 1e-6])
+        .collect::<Vec<_>>();
+    let coordinate = prop::sample::select(values);
+
+    prop::collection::vec((coordinate.clone(), coordinate.clone(), coordinate), 0..300)
+        .prop_map(|points| points.into_iter().map(|(x, y, z)| point(x, y, z)).collect())
+}
+
+fn cloud() -> impl Strategy<Value = Vec<Point>> {
+    prop_oneof![uniform(), clustered(), degenerate(), boundary()]
+}
+
+fn config() -> impl Strategy<Value = OctreeConfig> {
+    (
+        1..16usize,
+        1..10usize,
+        prop_oneof![Just(0.0), 0.1f32..10.0],
+        prop::sample::select(vec![
+            OutOfBoundsPolicy::Grow,
+            OutOfBoundsPolicy::Overflow,
+            OutOfBoundsPolicy::Reject,
+        ]),
+    )
+        .prop_map(
+            |(max_points, max_depth, min_cell_size, out_of_bounds)| OctreeConfig {
+                max_points,
+                max_depth,
+                min_cell_size,
+                out_of_bounds,
+            },
+        )
+}
+
+// Query point, radius, k and the AABB half extent
+fn queries() -> impl Strategy<Value = Vec<(Point, f32, usize, f32)>> {
+    prop::collection::vec(
+        (
+            point_in(-SIZE * 2.0, SIZE * 2.0),
+            0.0..SIZE,
+            0..20usize,
+            0.0..SIZE,
+        ),
+        1..8,
+    )
+}
+
+fn in_root(point: &Point) -> bool {
+    [point.x, point.y, point.z]
+        .iter()
+        .all(|value| (-SIZE..=SIZE).contains(value))
+}
+
+// Inserts the points one by one, returning the index of every accepted point
+fn insert_all(points: &[Point], config: OctreeConfig) -> (Octree<usize>, Vec<Option<Index>>) {
+    let mut octree = Octree::with_config(config);
+    octree.create_root(
+        point(0.0, 0.0, 0.0),
+        point(-SIZE, -SIZE, -SIZE),
+        point(SIZE, SIZE, SIZE),
+    );
+
+    let indices = points
+        .iter()
+        .enumerate()
+        .map(|(i, point)| {
+            let result = octree.insert(point.clone(), i);
+
+            // Only the reject policy refuses points, and only those outside the root
+            let rejected =
+                octree.config.out_of_bounds == OutOfBoundsPolicy::Reject && !in_root(point);
+            assert_eq!(result.is_err(), rejected);
+
+            result.ok()
+        })
+        .collect();
+
+    (octree, indices)
+}
+
+fn check_invariants(octree: &Octree<usize>) -> Result<(), TestCaseError> {
+    let mut stored = 0;
+
+    for (node_index, node) in octree.nodes.iter() {
+        stored += node.points.len();
+
+        prop_assert!(node.children.is_empty() || node.children.len() == 8);
+        // Only leaves hold a points vector
+        prop_assert!(node.children.is_empty() || node.points.capacity() == 0);
+
+        for child in node.children.iter() {
+            prop_assert_eq!(octree.nodes.get(*child).unwrap().parent, Some(node_index));
+        }
+
+        // Only leaves that can not be split any further may hold more than max_points
+        if node.points.len() > octree.config.max_points && Some(node_index) != octree.overflow {
+            prop_assert!(!octree.can_subdivide(node_index));
+        }
+    }
+
+    // Every point lives in exactly one leaf
+    prop_assert_eq!(stored, octree.points.len());
+
+    for (index, point) in octree.points.iter() {
+        let leaf = point.leaf.unwrap();
+        let node = octree.nodes.get(leaf).unwrap();
+
+        prop_assert!(node.children.is_empty());
+        prop_assert_eq!(
+            node.points.iter().filter(|other| **other == index).count(),
+            1
+        );
+
+        if Some(leaf) != octree.overflow {
+            prop_assert!(Octree::<usize>::is_point_in_bounds(node, &point.point));
+        }
+    }
+
+    Ok(())
+}
+
+// points[i] is the position of the point with data i, None if it is not in the octree
+fn check_queries(
+    octree: &Octree<usize>,
+    points: &[Option<Point>],
+    queries: &[(Point, f32, usize, f32)],
+) -> Result<(), TestCaseError> {
+    let stored = points
+        .iter()
+        .enumerate()
+        .filter_map(|(i, point)| point.as_ref().map(|point| (i, point)))
+        .collect::<Vec<_>>();
+
+    for (center, radius, k, extent) in queries {
+        let expected = stored
+            .iter()
+            .filter(|(_, point)| point.distance_squared(center) <= radius * radius)
+            .map(|(i, _)| *i)
+            .collect::<Vec<_>>();
+
+        let mut found = octree
+            .find_within_radius(center, *radius)
+            .into_iter()
+            .copied()
+            .collect::<Vec<_>>();
+        found.sort();
+        prop_assert_eq!(&found, &expected);
+
+        let mut found = vec![];
+        octree.for_each_neighbor(center, *radius, |i| found.push(*i));
+        found.sort();
+        prop_assert_eq!(&found, &expected);
+
+        // Ties make the order of equally distant points arbitrary, so only distances are compared
+        let mut expected = stored
+            .iter()
+            .map(|(_, point)| point.distance_squared(center))
+            .collect::<Vec<_>>();
+        expected.sort_by(|a, b| a.total_cmp(b));
+        expected.truncate(*k);
+
+        let found = octree
+            .k_nearest(center, *k)
+            .into_iter()
+            .map(|i| points[*i].as_ref().unwrap().distance_squared(center))
+            .collect::<Vec<_>>();
+        prop_assert_eq!(found, expected);
+
+        let min = point(center.x - extent, center.y - extent, center.z - extent);
+        let max = point(center.x + extent, center.y + extent, center.z + extent);
+
+        let expected = stored
+            .iter()
+            .filter(|(_, point)| {
+                point.x >= min.x
+                    && point.x <= max.x
+                    && point.y >= min.y
+                    && point.y <= max.y
+                    && point.z >= min.z
+                    && point.z <= max.z
+            })
+            .map(|(i, _)| *i)
+            .collect::<Vec<_>>();
+
+        let mut found = octree
+            .query_aabb(&min, &max)
+            .map(|(_, i)| *i)
+            .collect::<Vec<_>>();
+        found.sort();
+        prop_assert_eq!(found, expected);
+    }
+
+    // find_neighbors returns the whole leaf containing the point
+    for (i, point) in stored {
+        let neighbors = octree.find_neighbors(point);
+        prop_assert!(neighbors.contains(&&i));
+
+        let leaf = octree
+            .points
+            .iter()
+            .find(|(_, other)| other.data == i)
+            .unwrap()
+            .1
+            .leaf;
+
+        for neighbor in neighbors {
+            let other = octree
+                .points
+                .iter()
+                .find(|(_, other)| other.data == *neighbor)
+                .unwrap()
+                .1;
+            prop_assert_eq!(other.leaf, leaf);
+        }
+    }
+
+    Ok(())
+}
+
+proptest! {
+    #[test]
+    fn inserted_points_match_brute_force(
+        points in cloud(),
+        config in config(),
+        queries in queries(),
+    ) {
+        let (octree, indices) = insert_all(&points, config);
+
+        let stored = indices
+            .iter()
+            .zip(points.iter())
+            .map(|(index, point)| index.map(|_| point.clone()))
+            .collect::<Vec<_>>();
+
+        check_invariants(&octree)?;
+        check_queries(&octree, &stored, &queries)?;
+    }
+
+    #[test]
+    fn built_points_match_brute_force(
+        points in cloud(),
+        config in config(),
+        queries in queries(),
+    ) {
+        let octree = Octree::build_from(
+            points.iter().cloned().zip(0..).collect(),
+            config,
+        )
+        .unwrap();
+
+        let stored = points.iter().cloned().map(Some).collect::<Vec<_>>();
+
+        check_invariants(&octree)?;
+        check_queries(&octree, &stored, &queries)?;
+    }
+
+    #[test]
+    fn updated_points_match_brute_force(
+        points in cloud(),
+        config in config(),
+        moves in prop::collection::vec((any::<prop::sample::Index>(), point_in(-SIZE * 1.5, SIZE * 1.5)), 0..100),
+        removals in prop::collection::vec(any::<prop::sample::Index>(), 0..50),
+        queries in queries(),
+    ) {
+        let (mut octree, indices) = insert_all(&points, config);
+
+        let mut stored = indices
+            .iter()
+            .zip(points.iter())
+            .map(|(index, point)| index.map(|_| point.clone()))
+            .collect::<Vec<_>>();
+
+        if !points.is_empty() {
+            for (selected, new_point) in moves {
+                let i = selected.index(points.len());
+
+                let index = match indices[i] {
+                    Some(index) => index,
+                    None => continue,
+                };
+
+                // A rejected move leaves the point where it was
+                let result = octree.update(index, new_point.clone());
+                let rejected =
+                    octree.config.out_of_bounds == OutOfBoundsPolicy::Reject && !in_root(&new_point);
+                prop_assert_eq!(result.is_err(), rejected);
+
+                if !rejected {
+                    stored[i] = Some(new_point);
+                }
+            }
+
+            for selected in removals {
+                let i = selected.index(points.len());
+
+                if let Some(index) = indices[i] {
+                    let removed = octree.remove(index);
+
+                    prop_assert_eq!(removed, stored[i].take().map(|_| i));
+                }
+            }
+        }
+
+        check_invariants(&octree)?;
+        check_queries(&octree, &stored, &queries)?;
+    }
+}
@@ -0,0 +1,167 @@
+use bevy::{
+    prelude::*,
+    render::{mesh::PrimitiveTopology, view::NoFrustumCulling},
+};
+
+use autonomous_characters_3d::{octree::OctreeNode, spatial_index::SpatialBackend};
+
+use crate::{vehicle::VehicleSimulation, GlobalState};
+
+// Draws the octree used by flock as a line wireframe
+pub struct OctreeOverlayPlugin;
+
+// How the wireframe of a node is colored
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum OverlayColoring {
+    // Hue changes with the depth of the node
+    #[default]
+    Depth,
+    // Leaves go from green (empty) to red (full), inner nodes are grey
+    Occupancy,
+}
+
+impl OverlayColoring {
+    pub const ALL: [OverlayColoring; 2] = [OverlayColoring::Depth, OverlayColoring::Occupancy];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            OverlayColoring::Depth => "Depth",
+            OverlayColoring::Occupancy => "Occupancy",
+        }
+    }
+}
+
+#[derive(Component)]
+struct OctreeOverlay;
+
+impl Plugin for OctreeOverlayPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_startup_system(spawn_overlay)
+            .add_system(update_overlay);
+    }
+}
+
+fn spawn_overlay(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+) {
+    commands.spawn((
+        PbrBundle {
+            mesh: meshes.add(Mesh::new(PrimitiveTopology::LineList)),
+            // Vertex colors only
+            material: materials.add(StandardMaterial {
+                base_color: Color::WHITE,
+                unlit: true,
+                ..default()
+            }),
+            visibility: Visibility { is_visible: false },
+            ..default()
+        },
+        // The bounding box would be computed once for the empty mesh
+        NoFrustumCulling,
+        OctreeOverlay,
+    ));
+}
+
+// Rebuilds the wireframe mesh from the octree every frame
+fn update_overlay(
+    vehicle_simulation: Res<VehicleSimulation>,
+    mut overlay_query: Query<(&Handle<Mesh>, &mut Visibility), With<OctreeOverlay>>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    state: Res<GlobalState>,
+) {
+    let (mesh, mut visibility) = overlay_query.single_mut();
+
+    // The other backends are rebuilt every frame and have no nodes to draw
+    if !state.show_octree || state.params.spatial_index != SpatialBackend::Octree {
+        visibility.is_visible = false;
+        return;
+    }
+
+    let simulation = &vehicle_simulation.simulation;
+    let octree = &simulation.octree;
+
+    let nodes = if state.octree_overlay_selected_only {
+        // Nothing is drawn until a vehicle is selected, or after it was removed
+        state
+            .selected_vehicle
+            .and_then(|id| simulation.octree_index(id))
+            .and_then(|index| octree.points.get(index))
+            .and_then(|point| point.leaf)
+            .map(|leaf| (leaf, octree.nodes.get(leaf).unwrap(), octree.depth(leaf)))
+            .into_iter()
+            .collect::<Vec<_>>()
+    } else {
+        octree.nodes_with_depth().collect::<Vec<_>>()
+    };
+
+    let mut positions = vec![];
+    let mut colors = vec![];
+
+    // The overflow bucket has no real bounds
+    for (_, node, depth) in nodes
+        .into_iter()
+        .filter(|(index, _, _)| Some(*index) != octree.overflow)
+    {
+        let color = match state.octree_overlay_coloring {
+            OverlayColoring::Depth => {
+                let max_depth = octree.config.max_depth.max(1) as f32;
+                Color::hsl(300.0 * depth as f32 / max_depth, 0.8, 0.5)
+            }
+            OverlayColoring::Occupancy if node.children.is_empty() => {
+                let full = node.points.len() as f32 / octree.config.max_points.max(1) as f32;
+                Color::rgb(full.min(1.0), 1.0 - full.min(1.0), 0.0)
+            }
+            OverlayColoring::Occupancy => Color::GRAY,
+        };
+
+        push_box(node, color, &mut positions, &mut colors);
+    }
+
+    if positions.is_empty() {
+        visibility.is_visible = false;
+        return;
+    }
+
+    visibility.is_visible = true;
+
+    let mesh = meshes.get_mut(mesh).unwrap();
+    // The PBR pipeline expects normals, unlit materials ignore them
+    mesh.insert_attribute(
+        Mesh::ATTRIBUTE_NORMAL,
+        vec![[0.0, 0.0, 0.0]; positions.len()],
+    );
+    mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, positions);
+    mesh.insert_attribute(Mesh::ATTRIBUTE_COLOR, colors);
+}
+
+// Adds the 12 edges of the node bounds as line segments
+fn push_box(
+    node: &OctreeNode,
+    color: Color,
+    positions: &mut Vec<[f32; 3]>,
+    colors: &mut Vec<[f32; 4]>,
+) {
+    let (_, min, max) = &node.bounds;
+
+    // Bit 0, 1 and 2 of the corner index select max for x, y and z
+    let corner = |i: usize| {
+        [
+            if i & 1 == 0 { min.x } else { max.x },
+            if i & 2 == 0 { min.y } else { max.y },
+            if i & 4 == 0 { min.z } else { max.z },
+        ]
+    };
+
+    for i in 0..8 {
+        for axis in [1, 2, 4] {
+            if i & axis == 0 {
+                positions.push(corner(i));
+                positions.push(corner(i | axis));
+            }
+        }
+    }
+
+    colors.resize(positions.len(), color.as_linear_rgba_f32());
+}
@@ -0,0 +1,80 @@
+// Position used by every spatial index
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Point {
+    pub x: f32,
+    pub y: f32,
+    pub z: f32,
+}
+
+impl Point {
+    pub const ZERO: Point = Point {
+        x: 0.0,
+        y: 0.0,
+        z: 0.0,
+    };
+
+    pub fn is_finite(&self) -> bool {
+        self.x.is_finite() && self.y.is_finite() && self.z.is_finite()
+    }
+
+    // self += other * scale
+    pub fn add_scaled(&mut self, other: &Point, scale: f32) {
+        self.x += other.x * scale;
+        self.y += other.y * scale;
+        self.z += other.z * scale;
+    }
+
+    pub fn distance_squared(&self, other: &Point) -> f32 {
+        (self.x - other.x).powi(2) + (self.y - other.y).powi(2) + (self.z - other.z).powi(2)
+    }
+}
+
+// Conversion used by the public API of the spatial indices, so positions can be passed as
+// Point, [f32; 3], nalgebra::Vector3 or glam::Vec3 (with the glam feature)
+pub trait ToPoint {
+    fn to_point(&self) -> Point;
+}
+
+impl ToPoint for Point {
+    fn to_point(&self) -> Point {
+        self.clone()
+    }
+}
+
+impl ToPoint for [f32; 3] {
+    fn to_point(&self) -> Point {
+        Point {
+            x: self[0],
+            y: self[1],
+            z: self[2],
+        }
+    }
+}
+
+impl ToPoint for nalgebra::Vector3<f32> {
+    fn to_point(&self) -> Point {
+        Point {
+            x: self.x,
+            y: self.y,
+            z: self.z,
+        }
+    }
+}
+
+#[cfg(feature = "glam")]
+impl ToPoint for glam::Vec3 {
+    fn to_point(&self) -> Point {
+        Point {
+            x: self.x,
+            y: self.y,
+            z: self.z,
+        }
+    }
+}
+
+impl<P: ToPoint + ?Sized> ToPoint for &P {
+    fn to_point(&self) -> Point {
+        (**self).to_point()
+    }
+}
@@ -0,0 +1,94 @@
+use rand::{Error, RngCore};
+
+const GOLDEN_GAMMA: u64 = 0x9e37_79b9_7f4a_7c15;
+
+// Counter-based random numbers: every value is a hash of the seed, the stream and a counter,
+// so the numbers of a stream do not depend on the thread or the order other streams are used in
+#[derive(Debug, Clone)]
+pub struct CounterRng {
+    key: u64,
+    counter: u64,
+}
+
+impl CounterRng {
+    pub fn new(seed: u64, stream: u64, counter: u64) -> Self {
+        Self {
+            key: mix(seed ^ mix(stream.wrapping_add(GOLDEN_GAMMA))),
+            counter,
+        }
+    }
+}
+
+// Finalizer of SplitMix64
+fn mix(mut value: u64) -> u64 {
+    value = (value ^ (value >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    value = (value ^ (value >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    value ^ (value >> 31)
+}
+
+impl RngCore for CounterRng {
+    fn next_u32(&mut self) -> u32 {
+        (self.next_u64() >> 32) as u32
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        self.counter = self.counter.wrapping_add(1);
+
+        mix(self
+            .key
+            .wrapping_add(self.counter.wrapping_mul(GOLDEN_GAMMA)))
+    }
+
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        for chunk in dest.chunks_mut(8) {
+            let bytes = self.next_u64().to_le_bytes();
+            chunk.copy_from_slice(&bytes[..chunk.len()]);
+        }
+    }
+
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), Error> {
+        self.fill_bytes(dest);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::Rng;
+
+    use super::*;
+
+    #[test]
+    fn values_depend_only_on_seed_stream_and_counter() {
+        let draw = |seed, stream, counter| {
+            let mut rng = CounterRng::new(seed, stream, counter);
+            (0..4).map(|_| rng.next_u64()).collect::<Vec<_>>()
+        };
+
+        assert_eq!(draw(1, 2, 3), draw(1, 2, 3));
+        assert_ne!(draw(1, 2, 3), draw(2, 2, 3));
+        assert_ne!(draw(1, 2, 3), draw(1, 3, 3));
+
+        // Starting at a later counter skips the first values
+        assert_eq!(draw(1, 2, 3)[1..], draw(1, 2, 4)[..3]);
+    }
+
+    #[test]
+    fn neighboring_streams_look_uniform() {
+        let values = (0..10000)
+            .map(|stream| CounterRng::new(0, stream, 0).gen::<f32>())
+            .collect::<Vec<_>>();
+
+        let mean = values.iter().sum::<f32>() / values.len() as f32;
+        assert!((mean - 0.5).abs() < 0.02, "{mean}");
+
+        // Every tenth of the range gets about a tenth of the values
+        for bucket in 0..10 {
+            let count = values
+                .iter()
+                .filter(|value| (**value * 10.0) as usize == bucket)
+                .count();
+            assert!((800..1200).contains(&count), "{bucket} {count}");
+        }
+    }
+}
@@ -0,0 +1,1200 @@
+use std::{
+    f32::consts::PI,
+    ops::{Div, Mul},
+};
+
+#[cfg(feature = "octree")]
+use generational_arena::Index;
+use nalgebra::Vector3;
+use rand::Rng;
+use rayon::prelude::*;
+
+#[cfg(feature = "octree")]
+use crate::octree::{Approximation, Octree, OctreeConfig, OctreeStats, OutOfBoundsPolicy};
+use crate::{
+    integrator::Integrator,
+    point::{Point, ToPoint},
+    random::CounterRng,
+    spatial_index::{IndexConfig, NaiveIndex, PeriodicDomain, SpatialBackend, SpatialIndex},
+};
+
+// const WORLD_SIZE: Vector3<f32> = Vector3::new(200.0, 200.0, 100.0);
+pub const WORLD_SIZE: Vector3<f32> = Vector3::new(2000.0, 2000.0, 1000.0);
+
+// The steering forces were tuned as a change of velocity per frame at 60 frames per second
+const STEERING_RATE: f32 = 60.0;
+
+// Steps run by one call to advance at most, so slow frames do not pile up more and more steps
+const MAX_STEPS_PER_ADVANCE: usize = 8;
+
+// Random numbers a vehicle can draw per step, the counter of step n starts at (n + 1) * this.
+// Spawning draws from counter 0.
+const DRAWS_PER_STEP: u64 = 256;
+
+// Parameters of the boids algorithm and of the spatial index used for finding neighbors
+#[derive(Debug, Clone, PartialEq)]
+pub struct SimulationParams {
+    // Basic info
+    pub vehicle_size: f32,
+    pub vehicle_mass: f32,
+    pub vehicle_wander_speed: f32,
+
+    // Limits
+    pub vehicle_max_speed: f32,
+
+    // Factors
+    pub vehicle_seperation_factor: f32,
+    pub vehicle_alignment_factor: f32,
+    pub vehicle_cohesion_factor: f32,
+    pub vehicle_wander_factor: f32,
+    pub vehicle_wall_avoid_factor: f32,
+    pub vehicle_seek_factor: f32,
+
+    // Distances
+    pub vehicle_seperation_distance: f32,
+    pub vehicle_alignment_distance: f32,
+    pub vehicle_cohesion_distance: f32,
+    pub vehicle_wander_distance: f32,
+    pub vehicle_wander_radius: f32,
+
+    // Topological neighbors
+    pub use_topological: bool,
+    pub topological_neighbors: usize,
+
+    // Far vehicles are summarized by octree nodes (Barnes-Hut)
+    pub use_barnes_hut: bool,
+    pub barnes_hut_theta: f32,
+
+    // Vehicles leaving the world come back on the opposite side and see neighbors across it
+    pub periodic_world: bool,
+
+    // Integration, advance runs steps of fixed_timestep seconds
+    pub integrator: Integrator,
+    pub fixed_timestep: f32,
+
+    // Random spawn positions and wandering, the same seed gives the same trajectories
+    pub seed: u64,
+
+    // Spatial index, octree_size is also the leaf size of the k-d tree
+    pub spatial_index: SpatialBackend,
+    pub octree_rebuild: bool,
+    pub octree_size: usize,
+    pub octree_max_depth: usize,
+    pub octree_min_cell_size: f32,
+}
+
+impl Default for SimulationParams {
+    fn default() -> Self {
+        Self {
+            vehicle_size: 1.0,
+            vehicle_mass: 60.0,
+            vehicle_wander_speed: 40.0,
+
+            vehicle_max_speed: 80.0,
+
+            vehicle_seperation_factor: 1.5,
+            vehicle_alignment_factor: 3.0,
+            vehicle_cohesion_factor: 4.0,
+            vehicle_wander_factor: 1.0,
+            vehicle_wall_avoid_factor: 1.5,
+            vehicle_seek_factor: 7.0,
+
+            vehicle_seperation_distance: 4.0,
+            vehicle_alignment_distance: 30.0,
+            vehicle_cohesion_distance: 20.0,
+            vehicle_wander_distance: 4.0,
+            vehicle_wander_radius: 1.5,
+
+            use_topological: false,
+            topological_neighbors: 7,
+
+            use_barnes_hut: false,
+            barnes_hut_theta: 0.5,
+
+            periodic_world: false,
+
+            integrator: Integrator::default(),
+            fixed_timestep: 1.0 / 60.0,
+
+            seed: 0,
+
+            spatial_index: SpatialBackend::Naive,
+            octree_rebuild: false,
+            octree_size: 100,
+            octree_max_depth: 12,
+            octree_min_cell_size: 1.0,
+        }
+    }
+}
+
+impl SimulationParams {
+    // Largest distance at which other vehicles affect a vehicle
+    pub fn query_radius(&self) -> f32 {
+        self.vehicle_size
+            * self
+                .vehicle_seperation_distance
+                .max(self.vehicle_alignment_distance)
+                .max(self.vehicle_cohesion_distance)
+    }
+
+    #[cfg(feature = "octree")]
+    pub fn octree_config(&self) -> OctreeConfig {
+        OctreeConfig {
+            max_points: self.octree_size,
+            max_depth: self.octree_max_depth,
+            min_cell_size: self.octree_min_cell_size,
+            // Vehicles fly past WORLD_SIZE before wall avoidance turns them back
+            out_of_bounds: OutOfBoundsPolicy::Overflow,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct VehicleState {
+    pub position: Vector3<f32>,
+    // Position before the last step, for interpolating between steps
+    pub previous_position: Vector3<f32>,
+    pub velocity: Vector3<f32>,
+    pub acceleration: Vector3<f32>,
+    pub mass: f32,
+    // Angles of the wander target on the sphere in front of the vehicle
+    pub wander_theta: f32,
+    pub wander_phi: f32,
+    // Random number stream of the vehicle, the number of vehicles spawned before it
+    pub stream: u64,
+}
+
+impl VehicleState {
+    fn apply_force(&mut self, force: Vector3<f32>) {
+        self.acceleration += force / self.mass;
+    }
+}
+
+// Copy of a vehicle stored in the spatial index
+pub struct NeighborData {
+    // Position of the vehicle in Simulation::vehicles
+    pub id: usize,
+    pub position: Vector3<f32>,
+    pub velocity: Vector3<f32>,
+}
+
+// Flock of vehicles without any rendering, stepped by the Bevy plugin or headless
+pub struct Simulation {
+    pub params: SimulationParams,
+    pub vehicles: Vec<VehicleState>,
+    // Vehicles seek the target if there is one and wander otherwise
+    pub target: Option<Vector3<f32>>,
+    // Octree that persists between steps, updated or cleared and refilled in place
+    #[cfg(feature = "octree")]
+    pub octree: Octree<NeighborData>,
+    // Index of every vehicle in the octree, None until it is inserted
+    #[cfg(feature = "octree")]
+    octree_indices: Vec<Option<Index>>,
+    // Built every step instead of the octree above for the other backends, empty otherwise
+    index: Box<dyn SpatialIndex<NeighborData>>,
+    // Vehicles left out of the spatial index in the last step, their position is not finite
+    unindexed: usize,
+    // Time passed to advance that was not stepped yet, less than one timestep
+    accumulator: f32,
+    // Steps and spawned vehicles so far, counters of the random number streams
+    steps: u64,
+    spawned: u64,
+}
+
+// Summary statistics of the flock
+#[derive(Debug, Clone)]
+pub struct SimulationSummary {
+    pub vehicles: usize,
+    pub mean_speed: f32,
+    pub center_of_mass: Vector3<f32>,
+    // Mean distance to the center of mass
+    pub spread: f32,
+    // Length of the mean heading, 1 if all vehicles fly in the same direction
+    pub polarization: f32,
+    // Vehicles left out of the spatial index in the last step, their position is not finite
+    pub unindexed: usize,
+}
+
+impl Default for Simulation {
+    fn default() -> Self {
+        Self::new(SimulationParams::default())
+    }
+}
+
+impl Simulation {
+    pub fn new(params: SimulationParams) -> Self {
+        Self {
+            #[cfg(feature = "octree")]
+            octree: create_octree(params.octree_config()),
+            params,
+            vehicles: vec![],
+            target: None,
+            #[cfg(feature = "octree")]
+            octree_indices: vec![],
+            index: empty_index(),
+            unindexed: 0,
+            accumulator: 0.0,
+            steps: 0,
+            spawned: 0,
+        }
+    }
+
+    // The space inside the walls, glued at the faces when the world is periodic
+    pub fn world_domain() -> PeriodicDomain {
+        PeriodicDomain {
+            min: Point {
+                x: -WORLD_SIZE.x,
+                y: -WORLD_SIZE.y,
+                z: -WORLD_SIZE.z,
+            },
+            max: Point {
+                x: WORLD_SIZE.x,
+                y: WORLD_SIZE.y,
+                z: WORLD_SIZE.z,
+            },
+        }
+    }
+
+    // Adds a vehicle and returns its id, the position in vehicles
+    pub fn spawn(&mut self, position: Vector3<f32>, velocity: Vector3<f32>) -> usize {
+        self.vehicles.push(VehicleState {
+            position,
+            previous_position: position,
+            velocity,
+            acceleration: Vector3::zeros(),
+            mass: self.params.vehicle_mass,
+            wander_theta: 0.0,
+            wander_phi: 0.0,
+            stream: self.spawned,
+        });
+        self.spawned += 1;
+        #[cfg(feature = "octree")]
+        self.octree_indices.push(None);
+
+        self.vehicles.len() - 1
+    }
+
+    // Adds a vehicle at a random position flying at max speed
+    pub fn spawn_random(&mut self) -> usize {
+        let mut rng = CounterRng::new(self.params.seed, self.spawned, 0);
+
+        let position = Vector3::new(
+            -WORLD_SIZE.x / 2.0 + rng.gen::<f32>() * WORLD_SIZE.x,
+            -WORLD_SIZE.y / 2.0 + rng.gen::<f32>() * WORLD_SIZE.y,
+            -WORLD_SIZE.z / 2.0 + rng.gen::<f32>() * WORLD_SIZE.z,
+        );
+        let velocity = Vector3::new(
+            rng.gen::<f32>() * self.params.vehicle_max_speed,
+            rng.gen::<f32>() * self.params.vehicle_max_speed,
+            rng.gen::<f32>() * self.params.vehicle_max_speed,
+        )
+        .normalize()
+        .mul(self.params.vehicle_max_speed);
+
+        self.spawn(position, velocity)
+    }
+
+    // Removes the vehicle, the last vehicle takes its id
+    pub fn remove(&mut self, id: usize) -> VehicleState {
+        #[cfg(feature = "octree")]
+        {
+            if let Some(index) = self.octree_indices.swap_remove(id) {
+                self.octree.remove(index);
+            }
+
+            if let Some(Some(index)) = self.octree_indices.get(id) {
+                self.octree.get_mut(*index).unwrap().id = id;
+            }
+        }
+
+        self.vehicles.swap_remove(id)
+    }
+
+    // Index of the vehicle in the octree
+    #[cfg(feature = "octree")]
+    pub fn octree_index(&self, id: usize) -> Option<Index> {
+        self.octree_indices.get(id).copied().flatten()
+    }
+
+    // Vehicles left out of the spatial index in the last step, their position is not finite
+    pub fn unindexed(&self) -> usize {
+        self.unindexed
+    }
+
+    // Statistics of the octree, None while another backend is selected
+    #[cfg(feature = "octree")]
+    pub fn octree_stats(&self) -> Option<OctreeStats> {
+        if self.params.spatial_index == SpatialBackend::Octree {
+            Some(self.octree.stats())
+        } else {
+            None
+        }
+    }
+
+    // Runs as many fixed timesteps as fit into the elapsed time, the rest is carried over.
+    // Returns the number of steps.
+    pub fn advance(&mut self, elapsed: f32) -> usize {
+        let timestep = self.params.fixed_timestep;
+        let mut steps = 0;
+
+        self.accumulator += elapsed;
+
+        while self.accumulator >= timestep {
+            // Falling behind, the simulation runs slower than real time instead
+            if steps == MAX_STEPS_PER_ADVANCE {
+                self.accumulator %= timestep;
+                break;
+            }
+
+            self.step(timestep);
+            self.accumulator -= timestep;
+            steps += 1;
+        }
+
+        steps
+    }
+
+    // How far the carried over time is into the next step, from 0 to 1
+    pub fn interpolation(&self) -> f32 {
+        (self.accumulator / self.params.fixed_timestep).clamp(0.0, 1.0)
+    }
+
+    // Position between the last two steps for rendering, smooth when frames and steps differ
+    pub fn interpolated_position(&self, id: usize) -> Vector3<f32> {
+        let vehicle = &self.vehicles[id];
+        let travelled = vehicle.position - vehicle.previous_position;
+
+        // Wrapped around a periodic world, interpolating would cross the whole world
+        if (0..3).any(|axis| travelled[axis].abs() > WORLD_SIZE[axis]) {
+            return vehicle.position;
+        }
+
+        vehicle.previous_position + travelled * self.interpolation()
+    }
+
+    // Single step of dt seconds
+    pub fn step(&mut self, dt: f32) {
+        self.sync_index();
+        self.integrate(dt);
+        self.steps += 1;
+    }
+
+    // Keep the spatial index in sync with the vehicles
+    fn sync_index(&mut self) {
+        if self.params.spatial_index.is_kept_between_steps() {
+            self.index = empty_index();
+            #[cfg(feature = "octree")]
+            self.sync_octree();
+
+            return;
+        }
+
+        let points = self.indexed_points();
+        let config = IndexConfig {
+            #[cfg(feature = "octree")]
+            octree: self.params.octree_config(),
+            leaf_size: self.params.octree_size,
+            cell_size: self.params.query_radius(),
+        };
+
+        // A backend refusing the remaining points is replaced by comparing every pair this step
+        self.index = match self.params.spatial_index.build(points, &config) {
+            Ok(index) => index,
+            Err(_) => Box::new(NaiveIndex {
+                points: self.indexed_points(),
+            }),
+        };
+    }
+
+    // Vehicles with a finite position, the others are counted as unindexed
+    fn indexed_points(&mut self) -> Vec<(Point, NeighborData)> {
+        let points = self
+            .vehicles
+            .iter()
+            .enumerate()
+            .map(|(id, vehicle)| (vehicle.position.to_point(), neighbor_data(id, vehicle)))
+            .filter(|(point, _)| point.is_finite())
+            .collect::<Vec<_>>();
+
+        self.unindexed = self.vehicles.len() - points.len();
+
+        points
+    }
+
+    #[cfg(feature = "octree")]
+    fn sync_octree(&mut self) {
+        // The octree settings changed, start over
+        let config = self.params.octree_config();
+
+        if self.octree.config != config {
+            self.octree = create_octree(config);
+            self.octree_indices
+                .iter_mut()
+                .for_each(|index| *index = None);
+        }
+
+        // Built again in bulk, the storage of the previous step is reused
+        if self.params.octree_rebuild {
+            let points = self.indexed_points();
+            let ids = points.iter().map(|(_, data)| data.id).collect::<Vec<_>>();
+
+            self.octree_indices
+                .iter_mut()
+                .for_each(|index| *index = None);
+
+            match self.octree.rebuild(points) {
+                Ok(indices) => {
+                    for (id, index) in ids.into_iter().zip(indices) {
+                        self.octree_indices[id] = Some(index);
+                    }
+                }
+                // Only the reject policy refuses finite points, the octree stays empty this step
+                Err(_) => {
+                    self.octree.clear();
+                    self.unindexed = self.vehicles.len();
+                }
+            }
+        } else {
+            self.unindexed = 0;
+
+            for (id, vehicle) in self.vehicles.iter().enumerate() {
+                let point = vehicle.position.to_point();
+
+                let indexed = point.is_finite()
+                    && match self.octree_indices[id] {
+                        Some(index) => match self.octree.update(index, point) {
+                            Ok(_) => {
+                                *self.octree.get_mut(index).unwrap() = neighbor_data(id, vehicle);
+                                true
+                            }
+                            Err(_) => false,
+                        },
+                        None => match self.octree.insert(point, neighbor_data(id, vehicle)) {
+                            Ok(index) => {
+                                self.octree_indices[id] = Some(index);
+                                true
+                            }
+                            Err(_) => false,
+                        },
+                    };
+
+                // Left out until its position can be indexed again
+                if !indexed {
+                    if let Some(index) = self.octree_indices[id].take() {
+                        self.octree.remove(index);
+                    }
+
+                    self.unindexed += 1;
+                }
+            }
+        }
+
+        if self.params.use_barnes_hut {
+            self.octree
+                .update_summaries(|data| data.velocity.to_point());
+        }
+    }
+
+    // Boids algorithm, then seek or wander, integrated over dt. The integrator evaluates the
+    // forces at intermediate states of the vehicle, the neighbors stay where they were.
+    fn integrate(&mut self, dt: f32) {
+        let (params, target) = (&self.params, &self.target);
+        let counter = (self.steps + 1) * DRAWS_PER_STEP;
+
+        let index = self.index.as_ref();
+        // The octree kept between steps answers the queries instead
+        #[cfg(feature = "octree")]
+        let index: &dyn SpatialIndex<NeighborData> = if params.spatial_index.is_kept_between_steps()
+        {
+            &self.octree
+        } else {
+            index
+        };
+
+        let neighbors = Neighbors {
+            index,
+            // Node summaries are only kept by the octree and do not wrap around
+            #[cfg(feature = "octree")]
+            summaries: (params.use_barnes_hut
+                && !params.use_topological
+                && params.spatial_index == SpatialBackend::Octree
+                && !params.periodic_world)
+                .then_some(&self.octree),
+        };
+
+        let max_speed = params.vehicle_max_speed;
+        let domain = Self::world_domain();
+        let wrap = |position: &Vector3<f32>| {
+            if params.periodic_world {
+                let wrapped = domain.wrap(&position.to_point());
+                Vector3::new(wrapped.x, wrapped.y, wrapped.z)
+            } else {
+                *position
+            }
+        };
+
+        self.vehicles
+            .par_iter_mut()
+            .enumerate()
+            .for_each(|(id, vehicle)| {
+                if target.is_none() {
+                    let mut rng = CounterRng::new(params.seed, vehicle.stream, counter);
+                    turn_wander_target(vehicle, params, &mut rng);
+                }
+
+                let (mut position, mut velocity) = (vehicle.position, vehicle.velocity);
+
+                // The speed limit applies before a velocity moves the vehicle
+                params.integrator.step(
+                    &mut position,
+                    &mut velocity,
+                    dt,
+                    |position, velocity| {
+                        let mut state = VehicleState {
+                            position: wrap(position),
+                            velocity: *velocity,
+                            acceleration: Vector3::zeros(),
+                            ..vehicle.clone()
+                        };
+
+                        flock(id, &mut state, &neighbors, params);
+
+                        match target {
+                            Some(target) => seek(&mut state, target, params),
+                            None => wander(&mut state, params),
+                        }
+
+                        state.acceleration * STEERING_RATE
+                    },
+                    |velocity| velocity.map(|value| value.clamp(-max_speed, max_speed)),
+                );
+
+                vehicle.previous_position = vehicle.position;
+                vehicle.velocity = velocity;
+                vehicle.position = wrap(&position);
+            });
+    }
+
+    pub fn summary(&self) -> SimulationSummary {
+        let count = self.vehicles.len().max(1) as f32;
+
+        let center_of_mass = self
+            .vehicles
+            .iter()
+            .map(|vehicle| vehicle.position)
+            .sum::<Vector3<f32>>()
+            / count;
+        let heading = self
+            .vehicles
+            .iter()
+            .map(|vehicle| {
+                vehicle
+                    .velocity
+                    .try_normalize(f32::MIN_POSITIVE)
+                    .unwrap_or_else(Vector3::zeros)
+            })
+            .sum::<Vector3<f32>>()
+            / count;
+
+        SimulationSummary {
+            vehicles: self.vehicles.len(),
+            mean_speed: self
+                .vehicles
+                .iter()
+                .map(|vehicle| vehicle.velocity.magnitude())
+                .sum::<f32>()
+                / count,
+            center_of_mass,
+            spread: self
+                .vehicles
+                .iter()
+                .map(|vehicle| (vehicle.position - center_of_mass).magnitude())
+                .sum::<f32>()
+                / count,
+            polarization: heading.magnitude(),
+            unindexed: self.unindexed,
+        }
+    }
+}
+
+#[cfg(feature = "octree")]
+fn create_octree(config: OctreeConfig) -> Octree<NeighborData> {
+    let mut octree = Octree::with_config(config);
+    let domain = Simulation::world_domain();
+    octree.create_root(Point::ZERO, domain.min, domain.max);
+
+    octree
+}
+
+fn empty_index() -> Box<dyn SpatialIndex<NeighborData>> {
+    Box::new(NaiveIndex { points: vec![] })
+}
+
+fn neighbor_data(id: usize, vehicle: &VehicleState) -> NeighborData {
+    NeighborData {
+        id,
+        position: vehicle.position,
+        velocity: vehicle.velocity,
+    }
+}
+
+fn limit(data: &mut Vector3<f32>, max: f32) {
+    if data.magnitude_squared() > max * max {
+        *data = data.normalize() * max;
+    }
+}
+
+// Where flock looks for the neighbors of a vehicle
+struct Neighbors<'a> {
+    index: &'a dyn SpatialIndex<NeighborData>,
+    // Octree with node summaries when far vehicles are summarized (Barnes-Hut)
+    #[cfg(feature = "octree")]
+    summaries: Option<&'a Octree<NeighborData>>,
+}
+
+// Separation, alignment and cohesion with the neighbors of the vehicle
+fn flock(id: usize, vehicle: &mut VehicleState, neighbors: &Neighbors, params: &SimulationParams) {
+    let limit_seperate = params.vehicle_max_speed * params.vehicle_seperation_factor;
+    let limit_align = params.vehicle_max_speed * params.vehicle_alignment_factor;
+    let limit_cohesion = params.vehicle_max_speed * params.vehicle_cohesion_factor;
+    let dist_seperate = (params.vehicle_size * params.vehicle_seperation_distance).powi(2);
+    let dist_align = (params.vehicle_size * params.vehicle_alignment_distance).powi(2);
+    let dist_cohesion = (params.vehicle_size * params.vehicle_cohesion_distance).powi(2);
+    let query_radius = params.query_radius();
+
+    // All k nearest vehicles are used for alignment and cohesion
+    let (dist_align, dist_cohesion) = if params.use_topological {
+        (f32::MAX, f32::MAX)
+    } else {
+        (dist_align, dist_cohesion)
+    };
+
+    let mut seperate_sum = Vector3::new(0.0, 0.0, 0.0);
+    let mut align_sum = Vector3::new(0.0, 0.0, 0.0);
+    let mut cohesion_sum = Vector3::new(0.0, 0.0, 0.0);
+    let mut seperate_count = 0;
+    let mut align_count = 0;
+    let mut cohesion_count = 0;
+
+    let position = vehicle.position;
+    let point = position.to_point();
+
+    // Position and velocity of a single neighbor, None for the vehicle itself. Across the
+    // seam of a periodic world the neighbor is at the wrapped offset.
+    let exact = |neighbor: &NeighborData, offset: Option<&Point>| {
+        if id == neighbor.id {
+            return None;
+        }
+
+        let other_position = match offset {
+            Some(offset) => position + Vector3::new(offset.x, offset.y, offset.z),
+            None => neighbor.position,
+        };
+
+        Some((other_position, neighbor.velocity))
+    };
+
+    // A group of far vehicles counts as that many vehicles at its center of mass
+    let mut visit = |other_position: Vector3<f32>, other_velocity: Vector3<f32>, count: usize| {
+        let distance: f32 = (other_position - position).magnitude_squared();
+
+        // Groups are far away, separation only needs single vehicles
+        if distance < dist_seperate && count == 1 {
+            let mut diff = position - other_position;
+            diff = diff.normalize().div(distance.sqrt());
+            seperate_sum += diff;
+            seperate_count += 1;
+        }
+
+        if distance < dist_align {
+            align_sum += other_velocity;
+            align_count += count;
+        }
+
+        if distance < dist_cohesion {
+            cohesion_sum += other_position * count as f32;
+            cohesion_count += count;
+        }
+    };
+
+    let index = neighbors.index;
+
+    // Far vehicles are summarized instead of the radius query
+    #[cfg(feature = "octree")]
+    let summarized = match neighbors.summaries {
+        Some(octree) => {
+            octree.for_each_approximate(
+                &point,
+                query_radius,
+                params.barnes_hut_theta,
+                |neighbor| match neighbor {
+                    Approximation::Exact(neighbor) => {
+                        if let Some((other_position, other_velocity)) = exact(neighbor, None) {
+                            visit(other_position, other_velocity, 1);
+                        }
+                    }
+                    Approximation::Group(summary) => {
+                        let (center, velocity) = (&summary.center_of_mass, &summary.velocity_sum);
+
+                        visit(
+                            Vector3::new(center.x, center.y, center.z),
+                            Vector3::new(velocity.x, velocity.y, velocity.z),
+                            summary.count,
+                        );
+                    }
+                },
+            );
+
+            true
+        }
+        None => false,
+    };
+    #[cfg(not(feature = "octree"))]
+    let summarized = false;
+
+    if !summarized {
+        if params.use_topological {
+            // The vehicle itself is always the closest point
+            index
+                .k_nearest(&point, params.topological_neighbors + 1)
+                .into_iter()
+                .filter_map(|neighbor| exact(neighbor, None))
+                .for_each(|(other_position, other_velocity)| {
+                    visit(other_position, other_velocity, 1)
+                });
+        } else if params.periodic_world {
+            index.for_each_within_radius_periodic(
+                &point,
+                query_radius,
+                &Simulation::world_domain(),
+                &mut |offset, neighbor| {
+                    if let Some((other_position, other_velocity)) = exact(neighbor, Some(offset)) {
+                        visit(other_position, other_velocity, 1);
+                    }
+                },
+            );
+        } else {
+            index.for_each_within_radius(&point, query_radius, &mut |neighbor| {
+                if let Some((other_position, other_velocity)) = exact(neighbor, None) {
+                    visit(other_position, other_velocity, 1);
+                }
+            });
+        }
+    }
+
+    //Apply seperation
+    if seperate_count > 0 {
+        seperate_sum /= seperate_count as f32;
+        seperate_sum = seperate_sum.normalize() * params.vehicle_max_speed;
+        seperate_sum -= vehicle.velocity;
+        limit(&mut seperate_sum, limit_seperate);
+        vehicle.apply_force(seperate_sum);
+    }
+
+    // Apply alignment
+    if align_count > 0 {
+        align_sum /= align_count as f32;
+        align_sum = align_sum.normalize() * params.vehicle_max_speed;
+        align_sum -= vehicle.velocity;
+        limit(&mut align_sum, limit_align);
+        vehicle.apply_force(align_sum);
+    }
+
+    // Apply cohesion
+    if cohesion_count > 0 {
+        cohesion_sum /= cohesion_count as f32;
+        cohesion_sum -= position;
+        cohesion_sum = cohesion_sum
+            .try_normalize(f32::MIN)
+            .unwrap_or(Vector3::zeros());
+
+        let dist = cohesion_sum.magnitude();
+
+        if dist < 10.0 {
+            cohesion_sum *= dist / 10.0;
+        }
+
+        cohesion_sum *= params.vehicle_max_speed;
+        cohesion_sum -= vehicle.velocity;
+        limit(&mut cohesion_sum, limit_cohesion);
+        vehicle.apply_force(cohesion_sum);
+    }
+}
+
+fn seek(vehicle: &mut VehicleState, target: &Vector3<f32>, params: &SimulationParams) {
+    let limit_seek = params.vehicle_max_speed * params.vehicle_seek_factor;
+
+    // Calculate force
+    let mut force = (target - vehicle.position)
+        .normalize()
+        .mul(params.vehicle_max_speed);
+    force -= vehicle.velocity;
+
+    // Limit force
+    limit(&mut force, limit_seek);
+
+    // Apply force
+    vehicle.apply_force(force);
+}
+
+// Axes on which the vehicle left the world, a periodic world has no walls
+fn outside_walls(position: &Vector3<f32>, params: &SimulationParams) -> [bool; 3] {
+    let outside = |value: f32, size: f32| !params.periodic_world && (value < -size || value > size);
+
+    [0, 1, 2].map(|axis| outside(position[axis], WORLD_SIZE[axis]))
+}
+
+// Random walk of the wander target, once per step so every evaluation of the integrator
+// steers towards the same target
+fn turn_wander_target(vehicle: &mut VehicleState, params: &SimulationParams, rng: &mut impl Rng) {
+    let wander_delta = PI / 16.0;
+
+    if outside_walls(&vehicle.position, params).contains(&true) {
+        return;
+    }
+
+    let delta_theta = rng.gen_range(-wander_delta..=wander_delta);
+    let delta_phi = rng.gen_range(-wander_delta..=wander_delta);
+    vehicle.wander_theta += delta_theta;
+    vehicle.wander_phi += delta_phi;
+}
+
+// Steering force towards the wander target on a sphere in front, turned back by the walls
+fn wander(vehicle: &mut VehicleState, params: &SimulationParams) {
+    let limit_wander = params.vehicle_max_speed * params.vehicle_wander_factor;
+    let limit_wall_avoid = params.vehicle_max_speed * params.vehicle_wall_avoid_factor;
+
+    let [fx, fy, fz] = outside_walls(&vehicle.position, params);
+
+    if !fx && !fy && !fz {
+        let center = vehicle
+            .velocity
+            .normalize()
+            .mul(params.vehicle_wander_distance)
+            + vehicle.position;
+
+        let target = center
+            + Vector3::new(
+                vehicle.wander_theta.cos() * vehicle.wander_phi.sin(),
+                vehicle.wander_theta.sin() * vehicle.wander_phi.sin(),
+                vehicle.wander_phi.cos(),
+            )
+            .mul(params.vehicle_wander_radius);
+
+        // Calculate force
+        let mut force = (target - vehicle.position)
+            .normalize()
+            .mul(params.vehicle_wander_speed);
+        force -= vehicle.velocity;
+
+        // Limit force
+        limit(&mut force, limit_wander);
+
+        // Apply force
+        vehicle.apply_force(force);
+    }
+
+    // Calculate force, back towards the world on every axis the vehicle left it
+    let push = |outside: bool, value: f32| {
+        if !outside {
+            0.0
+        } else if value < 0.0 {
+            params.vehicle_max_speed
+        } else {
+            -params.vehicle_max_speed
+        }
+    };
+    let mut force = Vector3::new(
+        push(fx, vehicle.position.x),
+        push(fy, vehicle.position.y),
+        push(fz, vehicle.position.z),
+    );
+
+    force -= vehicle.velocity;
+
+    // Limit force
+    limit(&mut force, limit_wall_avoid);
+
+    // Apply force
+    vehicle.apply_force(force);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn random_simulation(count: usize, params: SimulationParams) -> Simulation {
+        let mut simulation = Simulation::new(params);
+
+        for _ in 0..count {
+            simulation.spawn_random();
+        }
+
+        simulation
+    }
+
+    #[test]
+    fn backends_give_the_same_trajectories() {
+        // Seeking is the only force without randomness
+        let mut reference = random_simulation(500, SimulationParams::default());
+        reference.target = Some(Vector3::new(10.0, -20.0, 30.0));
+
+        let start = reference.vehicles.clone();
+
+        for backend in SpatialBackend::ALL {
+            let mut simulation = Simulation::new(SimulationParams {
+                spatial_index: backend,
+                ..Default::default()
+            });
+            simulation.target = reference.target;
+
+            for vehicle in start.iter() {
+                simulation.spawn(vehicle.position, vehicle.velocity);
+            }
+
+            for _ in 0..10 {
+                simulation.step(1.0 / 60.0);
+            }
+
+            if backend == SpatialBackend::Naive {
+                reference = simulation;
+                continue;
+            }
+
+            // Neighbors are visited in a different order, so the sums can round differently
+            for (vehicle, expected) in simulation.vehicles.iter().zip(reference.vehicles.iter()) {
+                assert!(
+                    (vehicle.position - expected.position).magnitude() < 1e-2,
+                    "{:?}",
+                    backend
+                );
+            }
+        }
+    }
+
+    #[cfg(feature = "octree")]
+    #[test]
+    fn removed_vehicles_leave_the_octree() {
+        let mut simulation = random_simulation(
+            200,
+            SimulationParams {
+                spatial_index: SpatialBackend::Octree,
+                ..Default::default()
+            },
+        );
+        simulation.step(1.0 / 60.0);
+
+        for id in (0..200).step_by(3).rev() {
+            simulation.remove(id);
+        }
+
+        simulation.step(1.0 / 60.0);
+
+        assert_eq!(simulation.octree.points.len(), simulation.vehicles.len());
+
+        // Every vehicle is stored with its current id
+        for id in 0..simulation.vehicles.len() {
+            let data = simulation
+                .octree
+                .get(simulation.octree_index(id).unwrap())
+                .unwrap();
+
+            assert_eq!(data.id, id);
+        }
+    }
+
+    // Single vehicle seeking the target, no neighbors and no randomness
+    fn seeking_vehicle(integrator: Integrator, fixed_timestep: f32) -> Simulation {
+        let mut simulation = Simulation::new(SimulationParams {
+            integrator,
+            fixed_timestep,
+            ..Default::default()
+        });
+        simulation.target = Some(Vector3::new(100.0, 50.0, -20.0));
+        simulation.spawn(Vector3::zeros(), Vector3::new(0.0, -40.0, 0.0));
+
+        simulation
+    }
+
+    #[test]
+    fn seeking_converges_as_dt_shrinks() {
+        let position_after = |integrator: Integrator, dt: f32| {
+            let mut simulation = seeking_vehicle(integrator, dt);
+
+            for _ in 0..(1.0 / dt).round() as usize {
+                simulation.step(dt);
+            }
+
+            simulation.vehicles[0].position
+        };
+
+        let exact = position_after(Integrator::Rk4, 1.0 / 960.0);
+
+        for (integrator, order) in Integrator::ALL.into_iter().zip([1.0, 1.0, 2.0, 4.0]) {
+            let errors = [2.0, 4.0, 8.0, 16.0]
+                .map(|rate| (position_after(integrator, 1.0 / rate) - exact).magnitude());
+
+            assert!(
+                errors.windows(2).all(|pair| pair[1] < pair[0]),
+                "{integrator:?} {errors:?}"
+            );
+
+            // Halving dt divides the error by about 2^order
+            let observed = (errors[0] / errors[1]).log2();
+            assert!(observed > order - 0.5, "{integrator:?} {observed}");
+        }
+    }
+
+    #[test]
+    fn advance_does_not_depend_on_the_frame_rate() {
+        for integrator in Integrator::ALL {
+            let mut slow = seeking_vehicle(integrator, 1.0 / 64.0);
+            let mut fast = seeking_vehicle(integrator, 1.0 / 64.0);
+
+            let slow_steps = (0..32).map(|_| slow.advance(1.0 / 32.0)).sum::<usize>();
+            let fast_steps = (0..128).map(|_| fast.advance(1.0 / 128.0)).sum::<usize>();
+
+            assert_eq!(slow_steps, 64);
+            assert_eq!(fast_steps, 64);
+            assert_eq!(slow.vehicles[0].position, fast.vehicles[0].position);
+
+            // Half a step carried over, drawn halfway between the last two steps
+            fast.advance(1.0 / 128.0);
+            let vehicle = &fast.vehicles[0];
+
+            assert_eq!(fast.interpolation(), 0.5);
+            assert!(
+                (fast.interpolated_position(0)
+                    - (vehicle.previous_position + vehicle.position) / 2.0)
+                    .magnitude()
+                    < 1e-3
+            );
+        }
+    }
+
+    #[test]
+    fn seeded_trajectories_do_not_depend_on_the_thread_count() {
+        let run = |threads: usize, params: SimulationParams| {
+            let pool = rayon::ThreadPoolBuilder::new()
+                .num_threads(threads)
+                .build()
+                .unwrap();
+
+            pool.install(|| {
+                // Wandering vehicles draw random numbers every step
+                let mut simulation = random_simulation(300, params);
+
+                for _ in 0..20 {
+                    simulation.advance(1.0 / 30.0);
+                }
+
+                simulation
+                    .vehicles
+                    .iter()
+                    .map(|vehicle| (vehicle.position, vehicle.velocity))
+                    .collect::<Vec<_>>()
+            })
+        };
+
+        for backend in SpatialBackend::ALL {
+            let params = SimulationParams {
+                spatial_index: backend,
+                use_barnes_hut: true,
+                seed: 42,
+                ..Default::default()
+            };
+
+            let expected = run(1, params.clone());
+
+            assert_eq!(run(4, params.clone()), expected, "{backend:?}");
+            assert_ne!(
+                run(4, SimulationParams { seed: 43, ..params }),
+                expected,
+                "{backend:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn vehicles_without_a_finite_position_are_left_out_of_the_index() {
+        for backend in SpatialBackend::ALL {
+            for octree_rebuild in [false, true] {
+                let mut simulation = random_simulation(
+                    100,
+                    SimulationParams {
+                        spatial_index: backend,
+                        octree_rebuild,
+                        ..Default::default()
+                    },
+                );
+                simulation.step(1.0 / 60.0);
+
+                simulation.vehicles[3].position.x = f32::NAN;
+                simulation.vehicles[7].position.y = f32::INFINITY;
+                simulation.step(1.0 / 60.0);
+
+                assert_eq!(simulation.summary().unindexed, 2);
+                #[cfg(feature = "octree")]
+                if backend == SpatialBackend::Octree {
+                    assert_eq!(simulation.octree_index(3), None);
+                    assert_eq!(simulation.octree.stats().points, 98);
+                }
+
+                // Indexed again once the position is finite
+                simulation.vehicles[3].position = Vector3::zeros();
+                simulation.vehicles[7].position = Vector3::zeros();
+                simulation.step(1.0 / 60.0);
+
+                assert_eq!(simulation.summary().unindexed, 0);
+            }
+        }
+    }
+
+    #[test]
+    fn steps_never_move_faster_than_max_speed() {
+        for integrator in Integrator::ALL {
+            let mut simulation = random_simulation(
+                200,
+                SimulationParams {
+                    integrator,
+                    ..Default::default()
+                },
+            );
+
+            // Spawned faster than allowed, large steps give large accelerations
+            simulation.params.vehicle_max_speed /= 4.0;
+            let dt = 0.5;
+            let bound = simulation.params.vehicle_max_speed * dt * (1.0 + 1e-5);
+
+            for _ in 0..10 {
+                simulation.step(dt);
+
+                for vehicle in simulation.vehicles.iter() {
+                    let moved = vehicle.position - vehicle.previous_position;
+
+                    assert!(moved.amax() <= bound, "{integrator:?} {moved:?}");
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn periodic_world_keeps_vehicles_inside() {
+        let mut simulation = random_simulation(
+            200,
+            SimulationParams {
+                periodic_world: true,
+                ..Default::default()
+            },
+        );
+
+        for _ in 0..50 {
+            // Large steps throw the vehicles across the faces
+            simulation.step(20.0);
+
+            for vehicle in simulation.vehicles.iter() {
+                assert!((0..3).all(|axis| vehicle.position[axis].abs() <= WORLD_SIZE[axis]));
+            }
+        }
+    }
+}
@@ -0,0 +1,508 @@
+use std::fmt;
+
+use crate::{
+    grid::{HashedGrid, UniformGrid},
+    kd_tree::KdTree,
+    point::Point,
+};
+#[cfg(feature = "octree")]
+use crate::{
+    linear_octree::LinearOctree,
+    loose_octree::LooseOctree,
+    octree::{Octree, OctreeConfig},
+};
+
+// Returned when a point can not be stored, outside the bounds or not finite
+#[derive(Debug)]
+pub enum SpatialIndexError {
+    OutOfBounds(Point),
+}
+
+impl fmt::Display for SpatialIndexError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SpatialIndexError::OutOfBounds(point) => {
+                write!(f, "point {:?} is outside the index bounds", point)
+            }
+        }
+    }
+}
+
+impl std::error::Error for SpatialIndexError {}
+
+// Points that are not finite can not be stored by any index
+pub(crate) fn check_finite<T>(points: &[(Point, T)]) -> Result<(), SpatialIndexError> {
+    match points.iter().find(|(point, _)| !point.is_finite()) {
+        Some((point, _)) => Err(SpatialIndexError::OutOfBounds(point.clone())),
+        None => Ok(()),
+    }
+}
+
+// Smallest axis-aligned box (min, max) containing the sphere
+pub(crate) fn sphere_aabb(center: &Point, radius: f32) -> (Point, Point) {
+    (
+        Point {
+            x: center.x - radius,
+            y: center.y - radius,
+            z: center.z - radius,
+        },
+        Point {
+            x: center.x + radius,
+            y: center.y + radius,
+            z: center.z + radius,
+        },
+    )
+}
+
+// Settings for building any of the spatial indices
+#[derive(Debug, Clone, PartialEq)]
+pub struct IndexConfig {
+    // Used by the octrees
+    #[cfg(feature = "octree")]
+    pub octree: OctreeConfig,
+    // Points per leaf of the k-d tree
+    pub leaf_size: usize,
+    // Edge length of a grid cell, usually the largest query radius
+    pub cell_size: f32,
+}
+
+// Data structure answering the neighbor queries used by flock
+pub trait SpatialIndex<T>: Send + Sync {
+    // Builds the index from all points at once
+    fn build(points: Vec<(Point, T)>, config: &IndexConfig) -> Result<Self, SpatialIndexError>
+    where
+        Self: Sized;
+
+    // Calls visit with the position and data of every point within radius
+    fn for_each_point_within_radius(
+        &self,
+        point: &Point,
+        radius: f32,
+        visit: &mut dyn FnMut(&Point, &T),
+    );
+
+    // Calls visit for every point within radius
+    fn for_each_within_radius(&self, point: &Point, radius: f32, visit: &mut dyn FnMut(&T)) {
+        self.for_each_point_within_radius(point, radius, &mut |_, data| visit(data));
+    }
+
+    // Calls visit for every point within radius in a periodic domain, with the offset from the
+    // query point to the closest image of the point. Stored points have to be inside the domain.
+    fn for_each_within_radius_periodic(
+        &self,
+        point: &Point,
+        radius: f32,
+        domain: &PeriodicDomain,
+        visit: &mut dyn FnMut(&Point, &T),
+    ) {
+        let point = domain.wrap(point);
+        let size = domain.size();
+
+        // The query is repeated for every image of the point across the faces the sphere crosses
+        let shifts = |value: f32, min: f32, max: f32| {
+            [
+                (value + radius > max, -1.0),
+                (true, 0.0),
+                (value - radius < min, 1.0),
+            ]
+            .into_iter()
+            .filter(|(crosses, _)| *crosses)
+            .map(|(_, shift)| shift)
+        };
+
+        for shift_z in shifts(point.z, domain.min.z, domain.max.z) {
+            for shift_y in shifts(point.y, domain.min.y, domain.max.y) {
+                for shift_x in shifts(point.x, domain.min.x, domain.max.x) {
+                    let image = Point {
+                        x: point.x + shift_x * size.x,
+                        y: point.y + shift_y * size.y,
+                        z: point.z + shift_z * size.z,
+                    };
+
+                    self.for_each_point_within_radius(&image, radius, &mut |other, data| {
+                        // Large radii reach a point through several images, only the closest counts
+                        if domain.image_shift(&point, other) == [shift_x, shift_y, shift_z] {
+                            visit(
+                                &Point {
+                                    x: other.x - image.x,
+                                    y: other.y - image.y,
+                                    z: other.z - image.z,
+                                },
+                                data,
+                            );
+                        }
+                    });
+                }
+            }
+        }
+    }
+
+    // Returns the k closest points sorted by distance
+    fn k_nearest(&self, point: &Point, k: usize) -> Vec<&T>;
+}
+
+// Box whose opposite faces are glued together (toroidal world)
+#[derive(Debug, Clone)]
+pub struct PeriodicDomain {
+    pub min: Point,
+    pub max: Point,
+}
+
+impl PeriodicDomain {
+    pub fn size(&self) -> Point {
+        Point {
+            x: self.max.x - self.min.x,
+            y: self.max.y - self.min.y,
+            z: self.max.z - self.min.z,
+        }
+    }
+
+    // Moves the point into the domain
+    pub fn wrap(&self, point: &Point) -> Point {
+        let wrap = |value: f32, min: f32, max: f32| {
+            let wrapped = (value - min).rem_euclid(max - min);
+
+            // Rounding can give exactly the size for values just below min
+            if wrapped < max - min {
+                min + wrapped
+            } else {
+                min
+            }
+        };
+
+        Point {
+            x: wrap(point.x, self.min.x, self.max.x),
+            y: wrap(point.y, self.min.y, self.max.y),
+            z: wrap(point.z, self.min.z, self.max.z),
+        }
+    }
+
+    // Number of domain sizes between from and the image of to closest to it, per axis
+    fn image_shift(&self, from: &Point, to: &Point) -> [f32; 3] {
+        let size = self.size();
+
+        [
+            ((to.x - from.x) / size.x).round(),
+            ((to.y - from.y) / size.y).round(),
+            ((to.z - from.z) / size.z).round(),
+        ]
+    }
+
+    // Shortest vector from one point to the other (minimum image)
+    pub fn offset(&self, from: &Point, to: &Point) -> Point {
+        let size = self.size();
+        let [shift_x, shift_y, shift_z] = self.image_shift(from, to);
+
+        Point {
+            x: to.x - from.x - shift_x * size.x,
+            y: to.y - from.y - shift_y * size.y,
+            z: to.z - from.z - shift_z * size.z,
+        }
+    }
+}
+
+// Spatial indices that can be selected in the menu, the octrees need the octree feature
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum SpatialBackend {
+    #[default]
+    Naive,
+    #[cfg(feature = "octree")]
+    Octree,
+    #[cfg(feature = "octree")]
+    LinearOctree,
+    #[cfg(feature = "octree")]
+    LooseOctree,
+    UniformGrid,
+    HashedGrid,
+    KdTree,
+}
+
+impl SpatialBackend {
+    #[cfg(feature = "octree")]
+    pub const ALL: [SpatialBackend; 7] = [
+        SpatialBackend::Naive,
+        SpatialBackend::Octree,
+        SpatialBackend::LinearOctree,
+        SpatialBackend::LooseOctree,
+        SpatialBackend::UniformGrid,
+        SpatialBackend::HashedGrid,
+        SpatialBackend::KdTree,
+    ];
+
+    #[cfg(not(feature = "octree"))]
+    pub const ALL: [SpatialBackend; 4] = [
+        SpatialBackend::Naive,
+        SpatialBackend::UniformGrid,
+        SpatialBackend::HashedGrid,
+        SpatialBackend::KdTree,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            SpatialBackend::Naive => "Naive",
+            #[cfg(feature = "octree")]
+            SpatialBackend::Octree => "Octree",
+            #[cfg(feature = "octree")]
+            SpatialBackend::LinearOctree => "Linear octree",
+            #[cfg(feature = "octree")]
+            SpatialBackend::LooseOctree => "Loose octree",
+            SpatialBackend::UniformGrid => "Uniform grid",
+            SpatialBackend::HashedGrid => "Hashed grid",
+            SpatialBackend::KdTree => "K-d tree",
+        }
+    }
+
+    // Only the octree is kept between steps and updated, the others are built every step
+    pub fn is_kept_between_steps(&self) -> bool {
+        match self {
+            #[cfg(feature = "octree")]
+            SpatialBackend::Octree => true,
+            #[cfg(feature = "octree")]
+            SpatialBackend::LinearOctree | SpatialBackend::LooseOctree => false,
+            SpatialBackend::Naive
+            | SpatialBackend::UniformGrid
+            | SpatialBackend::HashedGrid
+            | SpatialBackend::KdTree => false,
+        }
+    }
+
+    pub fn build<T: Send + Sync + 'static>(
+        &self,
+        points: Vec<(Point, T)>,
+        config: &IndexConfig,
+    ) -> Result<Box<dyn SpatialIndex<T>>, SpatialIndexError> {
+        Ok(match self {
+            SpatialBackend::Naive => Box::new(NaiveIndex::build(points, config)?),
+            #[cfg(feature = "octree")]
+            SpatialBackend::Octree => Box::new(Octree::build(points, config)?),
+            #[cfg(feature = "octree")]
+            SpatialBackend::LinearOctree => Box::new(LinearOctree::build(points, config)?),
+            #[cfg(feature = "octree")]
+            SpatialBackend::LooseOctree => Box::new(LooseOctree::build(points, config)?),
+            SpatialBackend::UniformGrid => Box::new(UniformGrid::build(points, config)?),
+            SpatialBackend::HashedGrid => Box::new(HashedGrid::build(points, config)?),
+            SpatialBackend::KdTree => Box::new(KdTree::build(points, config)?),
+        })
+    }
+}
+
+// Compares every point, O(n) per query
+pub struct NaiveIndex<T> {
+    pub points: Vec<(Point, T)>,
+}
+
+impl<T: Send + Sync> SpatialIndex<T> for NaiveIndex<T> {
+    fn build(points: Vec<(Point, T)>, _: &IndexConfig) -> Result<Self, SpatialIndexError> {
+        Ok(Self { points })
+    }
+
+    fn for_each_point_within_radius(
+        &self,
+        point: &Point,
+        radius: f32,
+        visit: &mut dyn FnMut(&Point, &T),
+    ) {
+        for (other, data) in self.points.iter() {
+            if other.distance_squared(point) <= radius * radius {
+                visit(other, data);
+            }
+        }
+    }
+
+    fn k_nearest(&self, point: &Point, k: usize) -> Vec<&T> {
+        if k == 0 {
+            return vec![];
+        }
+
+        let mut distances = self
+            .points
+            .iter()
+            .map(|(other, data)| (other.distance_squared(point), data))
+            .collect::<Vec<_>>();
+
+        if distances.len() > k {
+            distances.select_nth_unstable_by(k - 1, |a, b| a.0.total_cmp(&b.0));
+            distances.truncate(k);
+        }
+
+        distances.sort_by(|a, b| a.0.total_cmp(&b.0));
+        distances.into_iter().map(|(_, data)| data).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::Rng;
+
+    use super::*;
+    use crate::test_utils::{random_point, test_rng, SIZE};
+
+    fn test_config() -> IndexConfig {
+        IndexConfig {
+            #[cfg(feature = "octree")]
+            octree: OctreeConfig {
+                max_points: 16,
+                ..Default::default()
+            },
+            leaf_size: 16,
+            cell_size: SIZE / 8.0,
+        }
+    }
+
+    #[test]
+    fn backends_match_naive() {
+        let mut rng = test_rng();
+
+        // Two clusters far apart, so the hashed grid has to search far for the k nearest
+        let points = (0..4000)
+            .map(|i| {
+                let point = random_point(&mut rng);
+                let offset = if i % 2 == 0 { 0.0 } else { SIZE * 50.0 };
+
+                Point {
+                    x: point.x + offset,
+                    y: point.y - offset,
+                    z: point.z,
+                }
+            })
+            .collect::<Vec<_>>();
+
+        let config = test_config();
+
+        let naive = SpatialBackend::Naive
+            .build(points.iter().cloned().zip(0..).collect(), &config)
+            .unwrap();
+
+        for backend in SpatialBackend::ALL {
+            let index = backend
+                .build(points.iter().cloned().zip(0..).collect(), &config)
+                .unwrap();
+
+            for i in 0..50 {
+                let center = if i % 10 == 0 {
+                    // Far outside all points
+                    Point {
+                        x: SIZE * 100.0,
+                        y: SIZE * 100.0,
+                        z: -SIZE * 100.0,
+                    }
+                } else {
+                    points[i * 37].clone()
+                };
+                // Some queries reach both clusters
+                let radius = if i % 5 == 4 {
+                    SIZE * 80.0
+                } else {
+                    rng.gen::<f32>() * SIZE / 2.0
+                };
+
+                let mut expected = vec![];
+                naive.for_each_within_radius(&center, radius, &mut |i| expected.push(*i));
+                expected.sort();
+
+                let mut found = vec![];
+                index.for_each_within_radius(&center, radius, &mut |i| found.push(*i));
+                found.sort();
+
+                assert_eq!(found, expected, "{:?}", backend);
+
+                // Far away points can have equal distances, so only the distances are compared
+                let distances = |nearest: Vec<&usize>| {
+                    nearest
+                        .iter()
+                        .map(|i| points[**i].distance_squared(&center))
+                        .collect::<Vec<_>>()
+                };
+
+                for k in [0, 1, 10, 3000] {
+                    assert_eq!(
+                        distances(index.k_nearest(&center, k)),
+                        distances(naive.k_nearest(&center, k)),
+                        "{:?}",
+                        backend
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn periodic_queries_match_brute_force() {
+        let mut rng = test_rng();
+        let domain = PeriodicDomain {
+            min: Point {
+                x: -SIZE,
+                y: -SIZE,
+                z: -SIZE / 2.0,
+            },
+            max: Point {
+                x: SIZE,
+                y: SIZE,
+                z: SIZE / 2.0,
+            },
+        };
+        let points = (0..4000)
+            .map(|_| domain.wrap(&random_point(&mut rng)))
+            .collect::<Vec<_>>();
+
+        let config = test_config();
+
+        for backend in SpatialBackend::ALL {
+            let index = backend
+                .build(points.iter().cloned().zip(0..).collect(), &config)
+                .unwrap();
+
+            for i in 0..50 {
+                // Near a corner, so the sphere crosses three faces, or outside the domain
+                let center = match i % 3 {
+                    0 => Point {
+                        x: SIZE - 1.0,
+                        y: -SIZE + 1.0,
+                        z: SIZE / 2.0 - 1.0,
+                    },
+                    1 => Point {
+                        x: points[i].x + SIZE * 2.0,
+                        y: points[i].y - SIZE * 4.0,
+                        z: points[i].z,
+                    },
+                    _ => random_point(&mut rng),
+                };
+                // Some queries are larger than half of the domain
+                let radius = if i % 5 == 4 {
+                    SIZE * 1.5
+                } else {
+                    rng.gen::<f32>() * SIZE / 2.0
+                };
+
+                let expected = points
+                    .iter()
+                    .enumerate()
+                    .map(|(i, point)| (i, domain.offset(&domain.wrap(&center), point)))
+                    .filter(|(_, offset)| offset.distance_squared(&Point::ZERO) <= radius * radius)
+                    .collect::<Vec<_>>();
+
+                let mut found = vec![];
+                index.for_each_within_radius_periodic(
+                    &center,
+                    radius,
+                    &domain,
+                    &mut |offset, i| found.push((*i, offset.clone())),
+                );
+                found.sort_by_key(|(i, _)| *i);
+
+                assert_eq!(found.len(), expected.len(), "{:?}", backend);
+
+                for ((i, offset), (expected_i, expected_offset)) in
+                    found.iter().zip(expected.iter())
+                {
+                    assert_eq!(i, expected_i, "{:?}", backend);
+                    assert!(
+                        offset.distance_squared(expected_offset) < 1e-6,
+                        "{:?}",
+                        backend
+                    );
+                }
+            }
+        }
+    }
+}
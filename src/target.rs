@@ -1,4 +1,5 @@
 use bevy::{pbr::NotShadowCaster, prelude::*};
+#[cfg(feature = "picking")]
 use bevy_mod_picking::{PickableBundle, Selection};
 
 use crate::RenderState;
@@ -7,8 +8,11 @@ pub struct TargetPlugin;
 
 impl Plugin for TargetPlugin {
     fn build(&self, app: &mut App) {
-        app.add_startup_system(spawn_target)
-            .add_system(input_controls);
+        app.add_startup_system(spawn_target);
+
+        // The target is moved only while it is selected
+        #[cfg(feature = "picking")]
+        app.add_system(input_controls);
     }
 }
 
@@ -30,11 +34,12 @@ fn spawn_target(
         }));
     }
 
-    commands
-        .spawn(SpatialBundle {
-            transform: Transform::from_translation(Vec3::new(0.0, 0.0, 0.0)),
-            ..Default::default()
-        })
+    let mut target = commands.spawn(SpatialBundle {
+        transform: Transform::from_translation(Vec3::new(0.0, 0.0, 0.0)),
+        ..Default::default()
+    });
+
+    target
         .insert(PbrBundle {
             mesh: meshes.get_handle(&render_state.mesh),
             material: materials.add(StandardMaterial {
@@ -45,11 +50,14 @@ fn spawn_target(
             ..Default::default()
         })
         .insert(NotShadowCaster)
-        .insert(Target)
-        .insert(PickableBundle::default());
+        .insert(Target);
+
+    #[cfg(feature = "picking")]
+    target.insert(PickableBundle::default());
 }
 
 // Move 3D object WASD + Space + Shift based on current camera position
+#[cfg(feature = "picking")]
 fn input_controls(
     keyboard_input: Res<Input<KeyCode>>,
     mut target_query: Query<(&mut Transform, &Selection), With<Target>>,
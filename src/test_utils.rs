@@ -0,0 +1,35 @@
+// Fixtures shared by the tests of the spatial indices
+use rand::Rng;
+
+use crate::{point::Point, random::CounterRng};
+
+// Random points are drawn from the cube from -SIZE to SIZE
+pub const SIZE: f32 = 100.0;
+
+// Seeded random numbers, so a failing test fails the same way on every run
+pub fn test_rng() -> CounterRng {
+    CounterRng::new(0, 0, 0)
+}
+
+pub fn random_point(rng: &mut impl Rng) -> Point {
+    Point {
+        x: (rng.gen::<f32>() - 0.5) * 2.0 * SIZE,
+        y: (rng.gen::<f32>() - 0.5) * 2.0 * SIZE,
+        z: (rng.gen::<f32>() - 0.5) * 2.0 * SIZE,
+    }
+}
+
+// Draws count random items and builds an index from them, the data of every item is its
+// position. The items are returned too, for comparing the index against a brute-force scan.
+#[cfg(feature = "octree")]
+pub fn random_index<R: Rng, I: Clone, O>(
+    rng: &mut R,
+    count: usize,
+    mut draw: impl FnMut(&mut R) -> I,
+    build: impl FnOnce(Vec<(I, usize)>) -> O,
+) -> (O, Vec<I>) {
+    let items = (0..count).map(|_| draw(rng)).collect::<Vec<_>>();
+    let index = build(items.iter().cloned().zip(0..).collect());
+
+    (index, items)
+}
@@ -1,312 +1,190 @@
-extern crate nalgebra as na;
-
-// const WORLD_SIZE: Vec3 = Vec3::new(200.0, 200.0, 100.0);
-const WORLD_SIZE: Vec3 = Vec3::new(2000.0, 2000.0, 1000.0);
-
-use std::{
-    f32::consts::PI,
-    ops::{Div, Mul},
-};
-
-use crate::{
-    octree::{Octree, Point},
-    target::Target,
-    GlobalState, RenderState,
-};
+#[cfg(feature = "octree")]
+use autonomous_characters_3d::octree::OctreeStats;
+use autonomous_characters_3d::{simulation::Simulation, spatial_index::SpatialBackend};
+use bevy::prelude::*;
+#[cfg(feature = "picking")]
 use bevy_mod_picking::Selection;
-use na::{SimdPartialOrd, Vector3};
+use rustc_hash::FxHashMap;
 
-use bevy::prelude::*;
-use rand::Rng;
+#[cfg(feature = "picking")]
+use crate::target::Target;
+use crate::{GlobalState, RenderState};
 
 pub struct VehiclePlugin;
 
 #[derive(Component)]
 pub struct Vehicle;
 
-#[derive(Component, Default)]
-struct VehicleVelocity(pub Vector3<f32>);
-
-#[derive(Component, Default)]
-struct VehicleAcceleration(pub Vector3<f32>);
-
-#[derive(Component, Default)]
-struct VehicleMass(f32);
-
-#[derive(Component, Default)]
-struct VehicleWanderRotation {
-    theta: f32,
-    phi: f32,
+// Simulation stepped every frame, the transforms of the vehicles mirror it
+#[derive(Default, Resource)]
+pub(crate) struct VehicleSimulation {
+    pub(crate) simulation: Simulation,
+    // Entity of every vehicle, in the order of Simulation::vehicles
+    entities: Vec<Entity>,
+    // Id of every vehicle in the simulation
+    pub(crate) ids: FxHashMap<Entity, usize>,
 }
 
+// Statistics of the octree shown in the menu, None while another backend is selected
+#[cfg(feature = "octree")]
 #[derive(Default, Resource)]
-struct VehicleSpawner {
-    vehicle_count: usize,
-}
+pub struct VehicleOctreeStats(pub Option<OctreeStats>);
 
 impl Plugin for VehiclePlugin {
     fn build(&self, app: &mut App) {
-        app.init_resource::<VehicleSpawner>()
-            .add_system(vehicle_spawner)
-            .add_system(vehicle_cleanup)
-            .add_system(movement)
-            .add_system(update);
-        // .add_system(benchmark);
-    }
-}
-
-impl VehicleAcceleration {
-    fn apply_force(&mut self, force: Vector3<f32>, mass: &VehicleMass) {
-        self.0 += force / mass.0;
+        app.init_resource::<VehicleSimulation>()
+            .add_system(vehicle_spawner.before(step_simulation))
+            .add_system(vehicle_cleanup.before(step_simulation))
+            .add_system(step_simulation)
+            .add_system(benchmark);
+
+        #[cfg(feature = "octree")]
+        app.init_resource::<VehicleOctreeStats>()
+            .add_system(octree_stats.after(step_simulation));
     }
 }
 
 fn vehicle_spawner(
     mut commands: Commands,
-    meshes: Res<Assets<Mesh>>,
-    materials: Res<Assets<StandardMaterial>>,
-    mut vehicle_spawner: ResMut<VehicleSpawner>,
+    mut vehicle_simulation: ResMut<VehicleSimulation>,
     state: Res<GlobalState>,
     render_state: Res<RenderState>,
-    assets_server: Res<AssetServer>,
 ) {
-    while state.vehicle_count > vehicle_spawner.vehicle_count {
-        // Cube at random position
-        commands
+    let VehicleSimulation {
+        simulation,
+        entities,
+        ids,
+    } = &mut *vehicle_simulation;
+
+    // Spawned with the current settings, the seed included
+    if simulation.params != state.params {
+        simulation.params = state.params.clone();
+    }
+
+    while state.vehicle_count > entities.len() {
+        // Vehicle at random position
+        let id = simulation.spawn_random();
+
+        let entity = commands
             .spawn(SceneBundle {
                 // scene: assets_server.load("cone.glb#Scene0"),
                 scene: render_state.vehicle_scene.clone(),
-                transform: Transform::from_translation(Vec3::new(
-                    -WORLD_SIZE.x / 2.0 + rand::random::<f32>() * WORLD_SIZE.x,
-                    -WORLD_SIZE.y / 2.0 + rand::random::<f32>() * WORLD_SIZE.y,
-                    -WORLD_SIZE.z / 2.0 + rand::random::<f32>() * WORLD_SIZE.z,
-                )),
+                transform: Transform::from_translation(simulation.vehicles[id].position.into()),
                 visibility: Visibility {
                     is_visible: if state.benchmark_mode { false } else { true },
                 },
                 ..Default::default()
             })
             .insert(Vehicle)
-            .insert(VehicleVelocity(
-                Vector3::new(
-                    rand::random::<f32>() * state.vehicle_max_speed,
-                    rand::random::<f32>() * state.vehicle_max_speed,
-                    rand::random::<f32>() * state.vehicle_max_speed,
-                )
-                .normalize()
-                .mul(state.vehicle_max_speed),
-            ))
-            .insert(VehicleAcceleration(Vector3::zeros()))
-            .insert(VehicleMass(state.vehicle_mass))
-            .insert(VehicleWanderRotation {
-                theta: 0.0,
-                phi: 0.0,
-            });
-        vehicle_spawner.vehicle_count += 1;
-    }
-}
-
-// Boids algorithm
-fn movement(
-    mut vehicle_query: Query<
-        (
-            Entity,
-            &VehicleVelocity,
-            &mut VehicleAcceleration,
-            &mut Transform,
-            &VehicleMass,
-            &mut VehicleWanderRotation,
-        ),
-        With<Vehicle>,
-    >,
-    target_query: Query<(&Transform, &Selection), (With<Target>, Without<Vehicle>)>,
-    state: Res<GlobalState>,
-) {
-    let (target_transform, selection) = target_query.get_single().unwrap();
-    let mut wander = true;
+            .id();
 
-    if selection.selected() {
-        wander = false;
-    }
-
-    if !wander {
-        let limit_seek = state.vehicle_max_speed * state.vehicle_seek_factor;
-
-        flock(&mut vehicle_query, &state);
-
-        // Seek
-        vehicle_query.par_for_each_mut(
-            64,
-            |(_, velocity, mut acceleration, transform, mass, _)| {
-                // Calculate force
-                let mut force: Vector3<f32> =
-                    (target_transform.translation - transform.translation).into();
-                force = force.normalize().mul(state.vehicle_max_speed);
-                force -= velocity.0;
-
-                // Limit force
-                limit(&mut force, limit_seek);
-
-                // Apply force
-                acceleration.apply_force(force, mass);
-            },
-        );
-    } else {
-        let wander_delta = PI / 16.0;
-
-        let limit_wander = state.vehicle_max_speed * state.vehicle_wander_factor;
-        let limit_wall_avoid = state.vehicle_max_speed * state.vehicle_wall_avoid_factor;
-
-        flock(&mut vehicle_query, &state);
-
-        // Wander (random steering force within a Sphere)
-        vehicle_query.par_for_each_mut(
-            64,
-            |(_, velocity, mut acceleration, transform, mass, mut wander_rotation)| {
-                // Check if in bounds
-                let fx = transform.translation.x < -WORLD_SIZE.x
-                    || transform.translation.x > WORLD_SIZE.x;
-                let fy = transform.translation.y < -WORLD_SIZE.y
-                    || transform.translation.y > WORLD_SIZE.y;
-
-                let fz = transform.translation.z < -WORLD_SIZE.z
-                    || transform.translation.z > WORLD_SIZE.z;
-
-                if !fx && !fy && !fz {
-                    let center = velocity.0.normalize().mul(state.vehicle_wander_distance)
-                        + Into::<Vector3<f32>>::into(transform.translation);
-                    let mut rng = rand::thread_rng();
-
-                    let delta_theta = rng.gen_range(-wander_delta..=wander_delta);
-                    let delta_phi = rng.gen_range(-wander_delta..=wander_delta);
-                    wander_rotation.theta += delta_theta;
-                    wander_rotation.phi += delta_phi;
-
-                    let target = center
-                        + Vector3::new(
-                            wander_rotation.theta.cos() * wander_rotation.phi.sin(),
-                            wander_rotation.theta.sin() * wander_rotation.phi.sin(),
-                            wander_rotation.phi.cos(),
-                        )
-                        .mul(state.vehicle_wander_radius);
-
-                    // Calculate force
-                    let mut force: Vector3<f32> =
-                        (target - Into::<Vector3<f32>>::into(transform.translation)).into();
-                    force = force.normalize().mul(state.vehicle_wander_speed);
-                    force -= velocity.0;
-
-                    // Limit force
-                    limit(&mut force, limit_wander);
-
-                    // Apply force
-                    acceleration.apply_force(force, mass);
-                }
-
-                // Calculate force
-                let mut force = Vector3::new(
-                    if fx {
-                        if transform.translation.x < -WORLD_SIZE.x {
-                            state.vehicle_max_speed
-                        } else {
-                            -state.vehicle_max_speed
-                        }
-                    } else {
-                        0.0
-                    },
-                    if fy {
-                        if transform.translation.y < -WORLD_SIZE.y {
-                            state.vehicle_max_speed
-                        } else {
-                            -state.vehicle_max_speed
-                        }
-                    } else {
-                        0.0
-                    },
-                    if fz {
-                        if transform.translation.z < -WORLD_SIZE.z {
-                            state.vehicle_max_speed
-                        } else {
-                            -state.vehicle_max_speed
-                        }
-                    } else {
-                        0.0
-                    },
-                );
-
-                force -= velocity.0;
-
-                // Limit force
-                limit(&mut force, limit_wall_avoid);
-
-                // Apply force
-                acceleration.apply_force(force, mass);
-            },
-        );
+        entities.push(entity);
+        ids.insert(entity, id);
     }
 }
 
 fn vehicle_cleanup(
     mut commands: Commands,
-    mut vehicle_spawner: ResMut<VehicleSpawner>,
-    state: Res<GlobalState>,
-    mut query: Query<Entity, With<Vehicle>>,
+    mut vehicle_simulation: ResMut<VehicleSimulation>,
+    mut state: ResMut<GlobalState>,
 ) {
-    if state.vehicle_count >= vehicle_spawner.vehicle_count {
-        return;
+    let VehicleSimulation {
+        simulation,
+        entities,
+        ids,
+    } = &mut *vehicle_simulation;
+
+    // Start over from the seed, the spawner adds the vehicles again
+    if state.restart_simulation {
+        for entity in entities.drain(..) {
+            commands.entity(entity).despawn_recursive();
+        }
+
+        ids.clear();
+        *simulation = Simulation::new(state.params.clone());
+        state.restart_simulation = false;
     }
 
-    for entity in &mut query {
+    // The last vehicles are removed, so no other vehicle changes its id
+    while state.vehicle_count < entities.len() {
+        let entity = entities.pop().unwrap();
+
         commands.entity(entity).despawn_recursive();
-        vehicle_spawner.vehicle_count -= 1;
-        if state.vehicle_count >= vehicle_spawner.vehicle_count {
-            break;
-        }
+        simulation.remove(ids.remove(&entity).unwrap());
     }
 }
 
-fn update(
-    mut vehicle_query: Query<
-        (
-            Entity,
-            &mut VehicleVelocity,
-            &mut VehicleAcceleration,
-            &mut Transform,
-        ),
-        With<Vehicle>,
+// Advances the simulation by fixed timesteps with the settings from the menu and copies the
+// result, interpolated between the last two steps, to the transforms
+fn step_simulation(
+    mut vehicle_simulation: ResMut<VehicleSimulation>,
+    mut vehicle_query: Query<(Entity, &mut Transform), With<Vehicle>>,
+    #[cfg(feature = "picking")] target_query: Query<
+        (&Transform, &Selection),
+        (With<Target>, Without<Vehicle>),
     >,
     time: Res<Time>,
     state: Res<GlobalState>,
+    mut unindexed: Local<usize>,
 ) {
-    let min = Vector3::from_element(state.vehicle_max_speed * -1.0);
-    let max = Vector3::from_element(state.vehicle_max_speed);
+    let VehicleSimulation {
+        simulation, ids, ..
+    } = &mut *vehicle_simulation;
 
-    vehicle_query.par_for_each_mut(64, |(_, mut velocity, mut acceleration, mut transform)| {
-        velocity.0 = (velocity.0 + acceleration.0).simd_clamp(min, max);
+    if simulation.params != state.params {
+        simulation.params = state.params.clone();
+    }
 
-        transform.translation.x += velocity.0.x * time.delta_seconds();
-        transform.translation.y += velocity.0.y * time.delta_seconds();
-        transform.translation.z += velocity.0.z * time.delta_seconds();
+    // Vehicles seek the target while it is selected and wander otherwise
+    #[cfg(feature = "picking")]
+    {
+        let (target_transform, selection) = target_query.get_single().unwrap();
 
-        transform.rotation = Quat::from_rotation_arc(
-            Vec3::new(0.0, 1.0, 0.0).into(),
-            velocity.0.normalize().into(),
-        );
+        simulation.target = if selection.selected() {
+            Some(target_transform.translation.into())
+        } else {
+            None
+        };
+    }
 
-        acceleration.0 *= 0.0;
-    });
-}
+    simulation.advance(time.delta_seconds());
 
-fn limit(data: &mut Vector3<f32>, max: f32) {
-    if data.magnitude_squared() > max * max {
-        *data = data.normalize() * max;
+    // Reported when the number changes instead of every frame
+    if simulation.unindexed() != *unindexed {
+        *unindexed = simulation.unindexed();
+
+        if *unindexed > 0 {
+            warn!(
+                "{} vehicles left out of the spatial index, their position is not finite",
+                *unindexed
+            );
+        }
     }
+
+    let (simulation, ids) = (&*simulation, &*ids);
+
+    vehicle_query.par_for_each_mut(64, |(entity, mut transform)| {
+        // Removed this frame, the entity is despawned at the end of the stage
+        let id = match ids.get(&entity) {
+            Some(id) => *id,
+            None => return,
+        };
+        let vehicle = &simulation.vehicles[id];
+
+        transform.translation = simulation.interpolated_position(id).into();
+        transform.rotation = Quat::from_rotation_arc(
+            Vec3::new(0.0, 1.0, 0.0),
+            vehicle.velocity.normalize().into(),
+        );
+    });
 }
 
-struct OctreeData {
-    pub entity: Entity,
-    pub transform: Transform,
-    pub velocity: Vector3<f32>,
+#[cfg(feature = "octree")]
+fn octree_stats(
+    vehicle_simulation: Res<VehicleSimulation>,
+    mut octree_stats: ResMut<VehicleOctreeStats>,
+) {
+    octree_stats.0 = vehicle_simulation.simulation.octree_stats();
 }
 
 fn benchmark(time: Res<Time>, mut state: ResMut<GlobalState>) {
@@ -321,17 +199,37 @@ fn benchmark(time: Res<Time>, mut state: ResMut<GlobalState>) {
             state.benchmark_step += 1;
 
             println!(
-                "Octree [{}]: {} {}",
-                state.use_octree, state.vehicle_count, mean
+                "{} Rebuild [{}]: {} {}",
+                state.params.spatial_index.name(),
+                state.params.octree_rebuild,
+                state.vehicle_count,
+                mean
             );
 
+            // Every backend in menu order, the octree first rebuilt every frame and then updated
             if state.vehicle_count >= 100000 {
-                if state.use_octree {
-                    state.benchmark_mode = false;
-                    state.vehicle_count = 0;
+                if state.params.spatial_index.is_kept_between_steps() && state.params.octree_rebuild
+                {
+                    state.params.octree_rebuild = false;
+                } else {
+                    let position = SpatialBackend::ALL
+                        .iter()
+                        .position(|backend| *backend == state.params.spatial_index)
+                        .unwrap();
+
+                    match SpatialBackend::ALL.get(position + 1) {
+                        Some(backend) => {
+                            state.params.spatial_index = *backend;
+                            state.params.octree_rebuild = backend.is_kept_between_steps();
+                        }
+                        None => {
+                            state.params.spatial_index = SpatialBackend::Naive;
+                            state.benchmark_mode = false;
+                        }
+                    }
                 }
 
-                state.use_octree = !state.use_octree;
+                state.vehicle_count = 0;
             }
 
             let step = if state.vehicle_count < 1000 {
@@ -346,243 +244,3 @@ fn benchmark(time: Res<Time>, mut state: ResMut<GlobalState>) {
         }
     }
 }
-
-fn flock(
-    vehicle_query: &mut Query<
-        (
-            Entity,
-            &VehicleVelocity,
-            &mut VehicleAcceleration,
-            &mut Transform,
-            &VehicleMass,
-            &mut VehicleWanderRotation,
-        ),
-        With<Vehicle>,
-    >,
-    state: &Res<GlobalState>,
-) {
-    let limit_seperate = state.vehicle_max_speed * state.vehicle_seperation_factor;
-    let limit_align = state.vehicle_max_speed * state.vehicle_alignment_factor;
-    let limit_cohesion = state.vehicle_max_speed * state.vehicle_cohesion_factor;
-    let dist_seperate = (state.vehicle_size * state.vehicle_seperation_distance).powi(2);
-    let dist_align = (state.vehicle_size * state.vehicle_alignment_distance).powi(2);
-    let dist_cohesion = (state.vehicle_size * state.vehicle_cohesion_distance).powi(2);
-
-    if state.use_octree {
-        let mut octree = Octree::new(state.octree_size);
-        octree.create_root(
-            Point {
-                x: 0.0,
-                y: 0.0,
-                z: 0.0,
-            },
-            Point {
-                x: -WORLD_SIZE.x,
-                y: -WORLD_SIZE.y,
-                z: -WORLD_SIZE.z,
-            },
-            Point {
-                x: WORLD_SIZE.x,
-                y: WORLD_SIZE.y,
-                z: WORLD_SIZE.z,
-            },
-        );
-
-        // Insert all vehicles into octree
-        for (entity, velocity, _, transform, _, _) in &mut vehicle_query.iter() {
-            octree.insert(
-                Point {
-                    x: transform.translation.x,
-                    y: transform.translation.y,
-                    z: transform.translation.z,
-                },
-                OctreeData {
-                    entity,
-                    transform: transform.clone(),
-                    velocity: velocity.0,
-                },
-            );
-        }
-
-        // Find all neighbors for each vehicle
-        vehicle_query.par_for_each_mut(
-            64,
-            |(entity, velocity, mut acceleration, transform, mass, _)| {
-                let mut seperate_sum = Vector3::new(0.0, 0.0, 0.0);
-                let mut align_sum = Vector3::new(0.0, 0.0, 0.0);
-                let mut cohesion_sum = Vector3::new(0.0, 0.0, 0.0);
-                let mut seperate_count = 0;
-                let mut align_count = 0;
-                let mut cohesion_count = 0;
-
-                let neighbors = octree.find_neighbors(&Point {
-                    x: transform.translation.x,
-                    y: transform.translation.y,
-                    z: transform.translation.z,
-                });
-
-                neighbors.iter().for_each(|neighbor| {
-                    let (other_entity, other_transform, other_velocity) =
-                        (neighbor.entity, &neighbor.transform, &neighbor.velocity);
-
-                    if entity == other_entity {
-                        return;
-                    }
-
-                    let distance: f32 = Into::<Vector3<f32>>::into(
-                        other_transform.translation - transform.translation,
-                    )
-                    .magnitude_squared();
-
-                    if distance < dist_seperate {
-                        let mut diff = Into::<Vector3<f32>>::into(
-                            transform.translation - other_transform.translation,
-                        );
-                        diff = diff.normalize().div(distance.sqrt());
-                        seperate_sum += diff;
-                        seperate_count += 1;
-                    }
-
-                    if distance < dist_align {
-                        align_sum += other_velocity;
-                        align_count += 1;
-                    }
-
-                    if distance < dist_cohesion {
-                        cohesion_sum += Into::<Vector3<f32>>::into(other_transform.translation);
-                        cohesion_count += 1;
-                    }
-                });
-
-                //Apply seperation
-                if seperate_count > 0 {
-                    seperate_sum /= seperate_count as f32;
-                    seperate_sum = seperate_sum.normalize() * state.vehicle_max_speed;
-                    seperate_sum -= velocity.0;
-                    limit(&mut seperate_sum, limit_seperate);
-                    acceleration.apply_force(seperate_sum, mass);
-                }
-
-                // Apply alignment
-                if align_count > 0 {
-                    align_sum /= align_count as f32;
-                    align_sum = align_sum.normalize() * state.vehicle_max_speed;
-                    align_sum -= velocity.0;
-                    limit(&mut align_sum, limit_align);
-                    acceleration.apply_force(align_sum, mass);
-                }
-
-                // Apply cohesion
-                if cohesion_count > 0 {
-                    cohesion_sum /= cohesion_count as f32;
-                    cohesion_sum = cohesion_sum - Into::<Vector3<f32>>::into(transform.translation);
-                    cohesion_sum = cohesion_sum
-                        .try_normalize(f32::MIN)
-                        .unwrap_or(Vector3::zeros());
-
-                    let dist = cohesion_sum.magnitude();
-
-                    if dist < 10.0 {
-                        cohesion_sum *= dist / 10.0;
-                    }
-
-                    cohesion_sum *= state.vehicle_max_speed;
-                    cohesion_sum -= velocity.0;
-                    limit(&mut cohesion_sum, limit_cohesion);
-                    acceleration.apply_force(cohesion_sum, mass);
-                }
-            },
-        );
-
-        return;
-    }
-
-    let others = vehicle_query
-        .iter()
-        .map(|(entity, velocity, _, transform, _, _)| {
-            (entity.index(), velocity.0, transform.clone())
-        })
-        .collect::<Vec<_>>();
-
-    vehicle_query.par_for_each_mut(
-        64,
-        |(entity, velocity, mut acceleration, transform, mass, _)| {
-            let mut seperate_sum = Vector3::new(0.0, 0.0, 0.0);
-            let mut align_sum = Vector3::new(0.0, 0.0, 0.0);
-            let mut cohesion_sum = Vector3::new(0.0, 0.0, 0.0);
-            let mut seperate_count = 0;
-            let mut align_count = 0;
-            let mut cohesion_count = 0;
-
-            others
-                .iter()
-                .for_each(|(other_index, other_velocity, other_transform)| {
-                    if entity.index() == *other_index {
-                        return;
-                    }
-
-                    let distance: f32 = Into::<Vector3<f32>>::into(
-                        other_transform.translation - transform.translation,
-                    )
-                    .magnitude_squared();
-
-                    if distance < dist_seperate {
-                        let mut diff = Into::<Vector3<f32>>::into(
-                            transform.translation - other_transform.translation,
-                        );
-                        diff = diff.normalize().div(distance.sqrt());
-                        seperate_sum += diff;
-                        seperate_count += 1;
-                    }
-
-                    if distance < dist_align {
-                        align_sum += other_velocity;
-                        align_count += 1;
-                    }
-
-                    if distance < dist_cohesion {
-                        cohesion_sum += Into::<Vector3<f32>>::into(other_transform.translation);
-                        cohesion_count += 1;
-                    }
-                });
-
-            //Apply seperation
-            if seperate_count > 0 {
-                seperate_sum /= seperate_count as f32;
-                seperate_sum = seperate_sum.normalize() * state.vehicle_max_speed;
-                seperate_sum -= velocity.0;
-                limit(&mut seperate_sum, limit_seperate);
-                acceleration.apply_force(seperate_sum, mass);
-            }
-
-            // Apply alignment
-            if align_count > 0 {
-                align_sum /= align_count as f32;
-                align_sum = align_sum.normalize() * state.vehicle_max_speed;
-                align_sum -= velocity.0;
-                limit(&mut align_sum, limit_align);
-                acceleration.apply_force(align_sum, mass);
-            }
-
-            // Apply cohesion
-            if cohesion_count > 0 {
-                cohesion_sum /= cohesion_count as f32;
-                cohesion_sum = cohesion_sum - Into::<Vector3<f32>>::into(transform.translation);
-                cohesion_sum = cohesion_sum
-                    .try_normalize(f32::MIN)
-                    .unwrap_or(Vector3::zeros());
-
-                let dist = cohesion_sum.magnitude();
-
-                if dist < 10.0 {
-                    cohesion_sum *= dist / 10.0;
-                }
-
-                cohesion_sum *= state.vehicle_max_speed;
-                cohesion_sum -= velocity.0;
-                limit(&mut cohesion_sum, limit_cohesion);
-                acceleration.apply_force(cohesion_sum, mass);
-            }
-        },
-    );
-}